@@ -0,0 +1,132 @@
+/*
+    Times numeric loops on the stack machine against the tree-walking interpreter.
+    Run with `cargo bench`
+*/
+
+use my_project::interpreter::Interpreter;
+use std::time::{Duration, Instant};
+
+const PROGRAMS: [(&str, &str); 4] = [
+    (
+        "branches",
+        "
+        var i 0
+        var fizz 0
+        var buzz 0
+        var other 0
+        loop i < 100000
+            add i 1
+            set r i
+            div r 15
+            mul r 15
+            if r == i
+                add fizz 1
+                add buzz 1
+            else
+                set r3 i
+                div r3 3
+                mul r3 3
+                if r3 == i
+                    add fizz 1
+                else
+                    set r5 i
+                    div r5 5
+                    mul r5 5
+                    if r5 == i
+                        add buzz 1
+                    else
+                        add other 1
+                    end
+                end
+            end
+            switch r
+                case 1
+                    add other 0
+                case 2
+                    add other 0
+                case 3
+                    add other 0
+            endswitch
+        end
+        print \"{fizz} {buzz} {other}\"
+        ",
+    ),
+    (
+        "counting loop",
+        "
+        var i 0
+        var total 0
+        loop i < 200000
+            add i 1
+            add total i
+            div total 2
+        end
+        print total
+        ",
+    ),
+    (
+        "nested loops with floats",
+        "
+        var i 0
+        float sum 0.0
+        loop i < 300
+            add i 1
+            var j 0
+            loop j < 300
+                add j 1
+                add_f sum 0.5
+            end
+        end
+        print sum
+        ",
+    ),
+    (
+        "function calls",
+        "
+        function square x with
+            mul x x
+            return x
+        end
+        var i 0
+        var total 0
+        loop i < 20000
+            add i 1
+            call square 3 into s
+            add total s
+        end
+        print total
+        ",
+    ),
+];
+
+fn time(run: impl Fn() -> Vec<u8>) -> (Duration, Vec<u8>) {
+    let mut best = Duration::MAX;
+    let mut output = vec![];
+    for _ in 0..5 {
+        let start = Instant::now();
+        output = run();
+        best = best.min(start.elapsed());
+    }
+    (best, output)
+}
+
+fn main() {
+    for (name, program) in PROGRAMS {
+        let (compiled, compiled_output) = time(|| {
+            let mut interpreter = Interpreter::new_with_output_stream(vec![]);
+            interpreter.run(program).unwrap().clone()
+        });
+        let (walked, walked_output) = time(|| {
+            let mut interpreter = Interpreter::new_with_output_stream(vec![]);
+            interpreter.run_tree_walking(program).unwrap().clone()
+        });
+        assert_eq!(compiled_output, walked_output, "{} prints differently", name);
+        println!(
+            "{:<26} tree-walking {:>9.2?}  bytecode {:>9.2?}  speedup {:.1}x",
+            name,
+            walked,
+            compiled,
+            walked.as_secs_f64() / compiled.as_secs_f64()
+        );
+    }
+}
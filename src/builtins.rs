@@ -0,0 +1,390 @@
+use crate::error::{NekoError, Result};
+use crate::lexeme::{Lexeme, Span};
+use crate::value::Value;
+use std::cmp::Ordering;
+
+/*
+    A value passed to a builtin, with the position of the word it was read from so errors can point at it
+*/
+pub struct Argument {
+    pub value: Value,
+    pub span: Span,
+}
+
+/*
+    How many operands a builtin reads before `into`
+*/
+pub fn arity(keyword: Lexeme) -> usize {
+    match keyword {
+        Lexeme::Len
+        | Lexeme::Trim
+        | Lexeme::Upper
+        | Lexeme::Lower
+        | Lexeme::Sort
+        | Lexeme::SortDesc
+        | Lexeme::Reverse
+        | Lexeme::Sum
+        | Lexeme::Min
+        | Lexeme::Max
+        | Lexeme::Avg
+        | Lexeme::IsNone
+        | Lexeme::IsSome => 1,
+        Lexeme::Substr | Lexeme::Replace | Lexeme::Slice => 3,
+        _ => 2,
+    }
+}
+
+/*
+    How many bytes the result of a builtin will take, for the builtins whose result can be
+    far larger than their operands, so a memory limit can be checked before it is built
+*/
+pub fn allocation(keyword: Lexeme, arguments: &[Argument]) -> usize {
+    match (keyword, &arguments[0].value) {
+        (Lexeme::Repeat, Value::Str(text)) => match arguments[1].value {
+            Value::Int(count) if count > 0 => text.len().saturating_mul(count as usize),
+            _ => 0,
+        },
+        (Lexeme::Fill, Value::Int(size)) if *size > 0 => {
+            arguments[1].value.size().saturating_mul(*size as usize)
+        }
+        (Lexeme::Replace, Value::Str(text)) => match (&arguments[1].value, &arguments[2].value) {
+            (Value::Str(from), Value::Str(to)) if to.len() > from.len() => {
+                let matches = match from.is_empty() {
+                    true => text.chars().count() + 1,
+                    false => text.matches(from.as_str()).count(),
+                };
+                text.len() + matches.saturating_mul(to.len() - from.len())
+            }
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/*
+    Runs a builtin. Strings are handled as sequences of chars, so lengths and indices
+    count characters rather than bytes. `concat`, `index_of` and `reverse` work on arrays
+    as well as strings, depending on their first operand.
+*/
+pub fn call(keyword: Lexeme, arguments: &[Argument]) -> Result<Value> {
+    if let Value::Array(values) = &arguments[0].value {
+        if let Some(value) = call_on_array(keyword, values, arguments)? {
+            return Ok(value);
+        }
+    }
+    let value = match keyword {
+        Lexeme::Concat => {
+            let mut text = string(&arguments[0])?.to_owned();
+            text.push_str(string(&arguments[1])?);
+            Value::Str(text)
+        }
+        Lexeme::Len => match &arguments[0].value {
+            Value::Array(values) => Value::Int(values.len() as i32),
+            _ => Value::Int(string(&arguments[0])?.chars().count() as i32),
+        },
+        Lexeme::Substr => {
+            let chars: Vec<char> = string(&arguments[0])?.chars().collect();
+            let start = index(&arguments[1], chars.len())?;
+            let end = index(&arguments[2], chars.len())?;
+            if start > end {
+                return Err(NekoError::at(
+                    format!("Substring end {} is before its start {}", end, start),
+                    arguments[2].span,
+                ));
+            }
+            Value::Str(chars[start..end].iter().collect())
+        }
+        Lexeme::IndexOf => {
+            let text = string(&arguments[0])?;
+            let position = text
+                .find(string(&arguments[1])?)
+                .map(|byte| text[..byte].chars().count() as i32);
+            Value::Int(position.unwrap_or(-1))
+        }
+        Lexeme::Split => {
+            let text = string(&arguments[0])?;
+            let separator = string(&arguments[1])?;
+            let parts = if separator.is_empty() {
+                text.chars().map(|c| Value::Str(c.to_string())).collect()
+            } else {
+                text.split(separator)
+                    .map(|part| Value::Str(part.to_owned()))
+                    .collect()
+            };
+            Value::Array(parts)
+        }
+        Lexeme::Join => {
+            let parts = match &arguments[0].value {
+                Value::Array(values) => values,
+                other => return Err(expected("an array", other, arguments[0].span)),
+            };
+            let parts: Vec<String> = parts.iter().map(|part| part.to_string()).collect();
+            Value::Str(parts.join(string(&arguments[1])?))
+        }
+        Lexeme::Replace => Value::Str(
+            string(&arguments[0])?.replace(string(&arguments[1])?, string(&arguments[2])?),
+        ),
+        Lexeme::Trim => Value::Str(string(&arguments[0])?.trim().to_owned()),
+        Lexeme::Upper => Value::Str(string(&arguments[0])?.to_uppercase()),
+        Lexeme::Lower => Value::Str(string(&arguments[0])?.to_lowercase()),
+        Lexeme::StartsWith => {
+            Value::Int(string(&arguments[0])?.starts_with(string(&arguments[1])?) as i32)
+        }
+        Lexeme::EndsWith => {
+            Value::Int(string(&arguments[0])?.ends_with(string(&arguments[1])?) as i32)
+        }
+        Lexeme::Repeat => {
+            let count = int(&arguments[1])?;
+            if count < 0 {
+                return Err(NekoError::at(
+                    format!("Cannot repeat a string {} times", count),
+                    arguments[1].span,
+                ));
+            }
+            Value::Str(string(&arguments[0])?.repeat(count as usize))
+        }
+        Lexeme::Reverse => Value::Str(string(&arguments[0])?.chars().rev().collect()),
+        Lexeme::IsNone => Value::Int((arguments[0].value == Value::None) as i32),
+        Lexeme::IsSome => Value::Int((arguments[0].value != Value::None) as i32),
+        Lexeme::UnwrapOr => match &arguments[0].value {
+            Value::None => arguments[1].value.clone(),
+            value => value.clone(),
+        },
+        Lexeme::Get => match (&arguments[0].value, &arguments[1].value) {
+            (Value::Map(entries), Value::Str(key)) => {
+                entries.get(key).cloned().unwrap_or(Value::None)
+            }
+            (Value::Tuple(values), Value::Int(position)) => usize::try_from(*position)
+                .ok()
+                .and_then(|position| values.get(position))
+                .cloned()
+                .unwrap_or(Value::None),
+            (Value::Map(_), other) => {
+                return Err(expected("a string key", other, arguments[1].span))
+            }
+            (Value::Tuple(_), other) => {
+                return Err(expected("an integer", other, arguments[1].span))
+            }
+            (other, _) => return Err(expected("an array, tuple or map", other, arguments[0].span)),
+        },
+        Lexeme::Fill => {
+            let size = int(&arguments[0])?;
+            if size < 0 {
+                return Err(NekoError::at(
+                    format!("Cannot fill an array of size {}", size),
+                    arguments[0].span,
+                ));
+            }
+            Value::Array(vec![arguments[1].value.clone(); size as usize])
+        }
+        _ => {
+            let kind = match keyword {
+                Lexeme::Len
+                | Lexeme::Substr
+                | Lexeme::Split
+                | Lexeme::Replace
+                | Lexeme::Trim
+                | Lexeme::Upper
+                | Lexeme::Lower
+                | Lexeme::StartsWith
+                | Lexeme::EndsWith
+                | Lexeme::Repeat => "a string",
+                _ => "an array",
+            };
+            return Err(expected(kind, &arguments[0].value, arguments[0].span));
+        }
+    };
+    Ok(value)
+}
+
+/*
+    Runs a builtin on an array, or returns `None` when the builtin does not take an array first
+*/
+fn call_on_array(
+    keyword: Lexeme,
+    values: &[Value],
+    arguments: &[Argument],
+) -> Result<Option<Value>> {
+    let span = arguments[0].span;
+    let value = match keyword {
+        Lexeme::Sort | Lexeme::SortDesc => {
+            let mut sorted = values.to_vec();
+            check_comparable(&sorted, span)?;
+            sorted.sort_by(|a, b| compare(a, b).unwrap_or(Ordering::Equal));
+            if keyword == Lexeme::SortDesc {
+                sorted.reverse();
+            }
+            Value::Array(sorted)
+        }
+        Lexeme::Reverse => Value::Array(values.iter().rev().cloned().collect()),
+        Lexeme::Contains => {
+            let found = values.iter().any(|value| equal(value, &arguments[1].value));
+            Value::Int(found as i32)
+        }
+        Lexeme::IndexOf => {
+            let position = values
+                .iter()
+                .position(|value| equal(value, &arguments[1].value));
+            Value::Int(position.map_or(-1, |index| index as i32))
+        }
+        Lexeme::BinarySearch => {
+            check_comparable(values, span)?;
+            let needle = &arguments[1].value;
+            let mut found = -1;
+            let (mut low, mut high) = (0, values.len());
+            while low < high {
+                let middle = (low + high) / 2;
+                match compare(&values[middle], needle)
+                    .map_err(|message| NekoError::at(message, arguments[1].span))?
+                {
+                    Ordering::Less => low = middle + 1,
+                    Ordering::Greater => high = middle,
+                    Ordering::Equal => {
+                        found = middle as i32;
+                        break;
+                    }
+                }
+            }
+            Value::Int(found)
+        }
+        Lexeme::Sum => sum(values, span)?,
+        Lexeme::Min | Lexeme::Max => {
+            check_comparable(values, span)?;
+            let ordering = if keyword == Lexeme::Min {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+            let mut best = first(values, keyword, span)?;
+            for value in values {
+                if compare(value, best) == Ok(ordering) {
+                    best = value;
+                }
+            }
+            best.clone()
+        }
+        Lexeme::Avg => {
+            first(values, keyword, span)?;
+            let total = match sum(values, span)? {
+                Value::Int(total) => total as f32,
+                Value::Float(total) => total,
+                _ => unreachable!("sum is numeric"),
+            };
+            Value::Float(total / values.len() as f32)
+        }
+        Lexeme::Slice => {
+            let start = index(&arguments[1], values.len())?;
+            let end = index(&arguments[2], values.len())?;
+            if start > end {
+                return Err(NekoError::at(
+                    format!("Slice end {} is before its start {}", end, start),
+                    arguments[2].span,
+                ));
+            }
+            Value::Array(values[start..end].to_vec())
+        }
+        Lexeme::Concat => match &arguments[1].value {
+            Value::Array(others) => Value::Array([values, others].concat()),
+            other => return Err(expected("an array", other, arguments[1].span)),
+        },
+        Lexeme::Len => Value::Int(values.len() as i32),
+        Lexeme::Get => {
+            let position = int(&arguments[1])?;
+            usize::try_from(position)
+                .ok()
+                .and_then(|position| values.get(position))
+                .cloned()
+                .unwrap_or(Value::None)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+fn first(values: &[Value], keyword: Lexeme, span: Span) -> Result<&Value> {
+    values.first().ok_or_else(|| {
+        let name = format!("{:?}", keyword).to_lowercase();
+        NekoError::at(format!("Cannot take the {} of an empty array", name), span)
+    })
+}
+
+fn sum(values: &[Value], span: Span) -> Result<Value> {
+    let mut total = Value::Int(0);
+    for value in values {
+        total = match (total, value) {
+            (Value::Int(total), Value::Int(value)) => match total.checked_add(*value) {
+                Some(total) => Value::Int(total),
+                None => return Err(NekoError::at("Integer overflow in sum", span)),
+            },
+            (Value::Int(total), Value::Float(value)) => Value::Float(total as f32 + value),
+            (Value::Float(total), Value::Int(value)) => Value::Float(total + *value as f32),
+            (Value::Float(total), Value::Float(value)) => Value::Float(total + value),
+            (_, other) => return Err(expected("a number", other, span)),
+        };
+    }
+    Ok(total)
+}
+
+/*
+    Orders numbers by value, whether integers or floats, and strings alphabetically
+*/
+fn compare(a: &Value, b: &Value) -> std::result::Result<Ordering, String> {
+    let ordering = match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Float(b)) => (*a as f32).partial_cmp(b),
+        (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f32)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    ordering.ok_or_else(|| format!("Cannot compare {} with {}", a, b))
+}
+
+fn check_comparable(values: &[Value], span: Span) -> Result<()> {
+    for pair in values.windows(2) {
+        compare(&pair[0], &pair[1]).map_err(|message| NekoError::at(message, span))?;
+    }
+    if let [value] = values {
+        compare(value, value).map_err(|message| NekoError::at(message, span))?;
+    }
+    Ok(())
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match compare(a, b) {
+        Ok(ordering) => ordering == Ordering::Equal,
+        Err(_) => a == b,
+    }
+}
+
+fn string(argument: &Argument) -> Result<&str> {
+    match &argument.value {
+        Value::Str(text) => Ok(text),
+        other => Err(expected("a string", other, argument.span)),
+    }
+}
+
+fn int(argument: &Argument) -> Result<i32> {
+    match argument.value {
+        Value::Int(value) => Ok(value),
+        ref other => Err(expected("an integer", other, argument.span)),
+    }
+}
+
+/*
+    An index into a sequence of `len` items. `len` itself is allowed, as the end of a range
+*/
+fn index(argument: &Argument, len: usize) -> Result<usize> {
+    let value = int(argument)?;
+    if value < 0 || value as usize > len {
+        return Err(NekoError::at(
+            format!("Index {} is out of bounds for length {}", value, len),
+            argument.span,
+        ));
+    }
+    Ok(value as usize)
+}
+
+fn expected(kind: &str, found: &Value, span: Span) -> NekoError {
+    NekoError::at(format!("Expected {}, found {}", kind, found), span)
+}
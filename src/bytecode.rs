@@ -0,0 +1,454 @@
+use crate::error::{NekoError, Result};
+use crate::interpreter::{Function, Variant};
+use crate::lexeme::{Comparison, Lexeme, Span};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/*
+    A value an instruction reads: an entry of the constant pool, a slot of the current scope,
+    or a field path or constant looked up by name when the instruction runs
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Constant(usize),
+    Slot(usize),
+    Name(usize),
+}
+
+/*
+    Where an instruction stores a value: a slot of the current scope, or a field path or constant,
+    which is bound by name so that it is checked when the instruction runs
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Place {
+    Slot(usize),
+    Name(usize),
+}
+
+impl From<Place> for Operand {
+    fn from(place: Place) -> Self {
+        match place {
+            Place::Slot(slot) => Operand::Slot(slot),
+            Place::Name(name) => Operand::Name(name),
+        }
+    }
+}
+
+/*
+    The names of a scope, each with the slot its value is kept in. The compiler gives every name it meets
+    a slot, so the stack machine reaches a binding by its index rather than by hashing its name.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Slots {
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl Slots {
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    /*
+        The slot of `name`, which is given the next free one the first time it is asked for
+    */
+    pub fn resolve(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.get(name) {
+            return slot;
+        }
+        self.names.push(name.to_owned());
+        self.index.insert(name.to_owned(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub fn name(&self, slot: usize) -> &str {
+        &self.names[slot]
+    }
+
+    /*
+        Every name, in the order of its slot
+    */
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/*
+    One instruction of the stack machine. Every statement leaves the stack as it found it, except for
+    the collection and position a `foreach` keeps on it and the bound a `loop` compares against,
+    which stay there until the loop is left. Jump targets are indices into the code of the same chunk.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    Push(Operand),
+    Int(Operand),
+    Float(Operand),
+    Number(Operand),
+    Pop,
+    Store(Place),
+    StoreLocal(Place),
+    Annotate {
+        name: usize,
+        annotation: usize,
+    },
+    Element {
+        array: usize,
+        element_type: usize,
+    },
+    MakeArray(usize),
+    MakeTuple(usize),
+    MakeStruct(usize),
+    MakeMap(usize),
+    DefineConstant(usize),
+    DefineFunction(usize),
+    DefineEnum(usize),
+    Arithmetic(usize),
+    SqrtInt,
+    AbsInt,
+    Builtin {
+        keyword: Lexeme,
+        call: usize,
+    },
+    PopArray(usize),
+    Input(Lexeme),
+    Print(usize),
+    PrintFormat(usize),
+    Call(usize),
+    Return(usize),
+    Jump(usize),
+    JumpUnless {
+        comparison: Comparison,
+        target: usize,
+    },
+    LoopUnless {
+        comparison: Comparison,
+        operand: Operand,
+        target: usize,
+    },
+    Iterate(Operand),
+    Next(usize),
+    Switch(usize),
+    Match(usize),
+    Break,
+    Continue,
+    OutsideLoop,
+}
+
+/*
+    The operation of an arithmetic statement such as `add x y` or `mul_f x y`
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    AddF,
+    SubF,
+    MulF,
+    DivF,
+}
+
+impl Arithmetic {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Arithmetic::Add => "add",
+            Arithmetic::Sub => "sub",
+            Arithmetic::Mul => "mul",
+            Arithmetic::Div => "div",
+            Arithmetic::Pow => "pow",
+            Arithmetic::AddF => "add_f",
+            Arithmetic::SubF => "sub_f",
+            Arithmetic::MulF => "mul_f",
+            Arithmetic::DivF => "div_f",
+        }
+    }
+
+    pub fn on_floats(self) -> bool {
+        matches!(
+            self,
+            Arithmetic::AddF | Arithmetic::SubF | Arithmetic::MulF | Arithmetic::DivF
+        )
+    }
+
+    /*
+        Fails on division by zero, a negative exponent and results an integer can not hold
+    */
+    pub fn integers(self, left: i32, right: i32) -> Result<i32> {
+        let result = match self {
+            Arithmetic::Add => left.checked_add(right),
+            Arithmetic::Sub => left.checked_sub(right),
+            Arithmetic::Mul => left.checked_mul(right),
+            Arithmetic::Div if right == 0 => return Err(NekoError::new("Division by zero")),
+            Arithmetic::Div => left.checked_div(right),
+            Arithmetic::Pow => {
+                let exponent = u32::try_from(right)
+                    .map_err(|_| NekoError::new(format!("Negative exponent: {}", right)))?;
+                left.checked_pow(exponent)
+            }
+            _ => unreachable!("{:?} works on floats", self),
+        };
+        result.ok_or_else(|| NekoError::new(format!("Integer overflow in {}", self.keyword())))
+    }
+
+    pub fn floats(self, left: f32, right: f32) -> f32 {
+        match self {
+            Arithmetic::AddF => left + right,
+            Arithmetic::SubF => left - right,
+            Arithmetic::MulF => left * right,
+            Arithmetic::DivF => left / right,
+            _ => unreachable!("{:?} works on integers", self),
+        }
+    }
+}
+
+/*
+    An arithmetic statement. It runs as a single instruction that changes its target in place,
+    since reading the target and storing it again would go through the checks of a store twice.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArithmeticSite {
+    pub operation: Arithmetic,
+    pub target: Place,
+    pub operand: Operand,
+    pub operand_span: Span,
+}
+
+/*
+    The fields or keys a `MakeStruct` or `MakeMap` takes from the stack, in the order they were pushed.
+    `prototype` is the struct named after `as`, whose fields the new struct starts from.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub type_name: String,
+    pub prototype: Option<String>,
+    pub fields: Vec<String>,
+}
+
+/*
+    A `call` whose arguments are on the stack, with the names the result is bound to after `into`
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallSite {
+    pub target: String,
+    pub arguments: Vec<Span>,
+    pub into: Vec<Place>,
+    pub into_span: Span,
+}
+
+/*
+    The names a `foreach` binds on each pass and where the loop is left
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Iteration {
+    pub key: Option<Place>,
+    pub item: Place,
+    pub exit: usize,
+}
+
+/*
+    The cases of a `switch`, tried in order, and where the switch ends when none of them matches
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwitchTable {
+    pub cases: Vec<(i32, usize)>,
+    pub end: usize,
+}
+
+/*
+    A case of a `match`: the variant it covers, or `_`, the names its payload is bound to and where its code starts
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchCase {
+    pub variant: String,
+    pub bindings: Vec<Place>,
+    pub target: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchTable {
+    pub name: String,
+    pub cases: Vec<MatchCase>,
+}
+
+/*
+    Compiled code together with the pools its instructions point into. `spans` holds the position
+    in the source of every instruction, for errors, and `slots` the names of the scope the code runs in.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub slots: Rc<Slots>,
+    pub arithmetic: Vec<ArithmeticSite>,
+    pub layouts: Vec<Layout>,
+    pub calls: Vec<CallSite>,
+    pub builtins: Vec<Vec<Span>>,
+    pub iterations: Vec<Iteration>,
+    pub switches: Vec<SwitchTable>,
+    pub matches: Vec<MatchTable>,
+    pub functions: Vec<(String, Function)>,
+    pub enums: Vec<(String, Vec<Variant>)>,
+}
+
+impl Chunk {
+    /*
+        The word an operand was written as, for errors
+    */
+    pub fn word(&self, operand: Operand) -> String {
+        match operand {
+            Operand::Constant(index) => self.constants[index].to_string(),
+            Operand::Slot(slot) => self.slots.name(slot).to_owned(),
+            Operand::Name(index) => self.names[index].clone(),
+        }
+    }
+
+    /*
+        The name a place was written as
+    */
+    pub fn place(&self, place: Place) -> &str {
+        match place {
+            Place::Slot(slot) => self.slots.name(slot),
+            Place::Name(index) => &self.names[index],
+        }
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, op) in self.code.iter().enumerate() {
+            write!(f, "{:04} ", index)?;
+            match op {
+                Op::Push(operand) => write!(f, "push {}", self.word(*operand))?,
+                Op::Int(operand) => write!(f, "int {}", self.word(*operand))?,
+                Op::Float(operand) => write!(f, "float {}", self.word(*operand))?,
+                Op::Number(operand) => write!(f, "number {}", self.word(*operand))?,
+                Op::Store(place) => write!(f, "store {}", self.place(*place))?,
+                Op::StoreLocal(place) => write!(f, "store_local {}", self.place(*place))?,
+                Op::Annotate { name, annotation } => write!(
+                    f,
+                    "annotate {}: {}",
+                    self.names[*name], self.names[*annotation]
+                )?,
+                Op::Element { element_type, .. } => {
+                    write!(f, "element {}", self.names[*element_type])?
+                }
+                Op::MakeStruct(layout) | Op::MakeMap(layout) => {
+                    let layout = &self.layouts[*layout];
+                    let name = if matches!(op, Op::MakeMap(_)) {
+                        "make_map"
+                    } else {
+                        "make_struct"
+                    };
+                    write!(
+                        f,
+                        "{} {} {}",
+                        name,
+                        layout.type_name,
+                        layout.fields.join(" ")
+                    )?
+                }
+                Op::DefineConstant(name) => write!(f, "define_constant {}", self.names[*name])?,
+                Op::DefineFunction(index) => {
+                    write!(f, "define_function {}", self.functions[*index].0)?
+                }
+                Op::DefineEnum(index) => write!(f, "define_enum {}", self.enums[*index].0)?,
+                Op::Builtin { keyword, .. } => write!(f, "builtin {:?}", keyword)?,
+                Op::PopArray(name) => write!(f, "pop_array {}", self.names[*name])?,
+                Op::Input(keyword) => write!(f, "input {:?}", keyword)?,
+                Op::Print(name) => write!(f, "print {}", self.names[*name])?,
+                Op::PrintFormat(template) => write!(f, "print \"{}\"", self.constants[*template])?,
+                Op::Call(call) => {
+                    let call = &self.calls[*call];
+                    write!(f, "call {} {}", call.target, call.arguments.len())?;
+                    if !call.into.is_empty() {
+                        let into: Vec<&str> =
+                            call.into.iter().map(|place| self.place(*place)).collect();
+                        write!(f, " into {}", into.join(" "))?;
+                    }
+                }
+                Op::Arithmetic(site) => {
+                    let site = &self.arithmetic[*site];
+                    write!(
+                        f,
+                        "{} {} {}",
+                        site.operation.keyword(),
+                        self.place(site.target),
+                        self.word(site.operand)
+                    )?
+                }
+                Op::LoopUnless {
+                    comparison,
+                    operand,
+                    target,
+                } => write!(
+                    f,
+                    "loop_unless {} {} {:04}",
+                    self.word(*operand),
+                    comparison,
+                    target
+                )?,
+                Op::Iterate(operand) => write!(f, "iterate {}", self.word(*operand))?,
+                Op::Next(iteration) => {
+                    let iteration = &self.iterations[*iteration];
+                    let item = self.place(iteration.item);
+                    write!(f, "next {} -> {:04}", item, iteration.exit)?
+                }
+                Op::Switch(table) => {
+                    let table = &self.switches[*table];
+                    write!(f, "switch")?;
+                    for (value, target) in &table.cases {
+                        write!(f, " {} -> {:04}", value, target)?;
+                    }
+                    write!(f, " else -> {:04}", table.end)?;
+                }
+                Op::Match(table) => {
+                    let table = &self.matches[*table];
+                    write!(f, "match {}", table.name)?;
+                    for case in &table.cases {
+                        write!(f, " {} -> {:04}", case.variant, case.target)?;
+                    }
+                }
+                other => write!(f, "{}", mnemonic(other))?,
+            }
+            writeln!(f)?;
+        }
+        for (name, function) in &self.functions {
+            writeln!(f, "\nfunction {}:", name)?;
+            write!(f, "{}", function.chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn mnemonic(op: &Op) -> String {
+    match op {
+        Op::Pop => "pop".to_owned(),
+        Op::MakeArray(size) => format!("make_array {}", size),
+        Op::MakeTuple(size) => format!("make_tuple {}", size),
+        Op::SqrtInt => "sqrt_int".to_owned(),
+        Op::AbsInt => "abs_int".to_owned(),
+        Op::Return(count) => format!("return {}", count),
+        Op::Jump(target) => format!("jump {:04}", target),
+        Op::JumpUnless { comparison, target } => {
+            format!("jump_unless {} {:04}", comparison, target)
+        }
+        Op::Break => "break".to_owned(),
+        Op::Continue => "continue".to_owned(),
+        Op::OutsideLoop => "outside_loop".to_owned(),
+        other => format!("{:?}", other),
+    }
+}
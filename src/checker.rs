@@ -0,0 +1,1077 @@
+use crate::builtins;
+use crate::error::{NekoError, Result};
+use crate::format;
+use crate::lexeme::{self, Lexeme, Span, Tokens, TupleElement, Word};
+use crate::types::{self, Signature, Type, BUILTIN_TYPES};
+use std::collections::{HashMap, HashSet};
+
+/*
+    Checks a program without running it. Every statement is read the way the interpreter reads it,
+    following the type of each binding as far as it can be known, so that unknown names, calls with
+    the wrong number of arguments, values of the wrong type and assignments to constants are reported
+    before the first line runs.
+*/
+pub struct Checker {
+    scope: HashMap<String, Type>,
+    constants: HashMap<String, Type>,
+    functions: HashMap<String, Signature>,
+    natives: HashSet<String>,
+    enums: HashMap<String, Vec<(String, usize)>>,
+    type_names: HashSet<String>,
+    returns: Option<String>,
+    loops: usize,
+    switches: usize,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            scope: HashMap::new(),
+            constants: HashMap::new(),
+            functions: HashMap::new(),
+            natives: HashSet::new(),
+            enums: HashMap::new(),
+            type_names: HashSet::new(),
+            returns: None,
+            loops: 0,
+            switches: 0,
+        }
+    }
+
+    /*
+        Makes a binding that already exists, for instance from an earlier run, known to the checker
+    */
+    pub fn define(&mut self, name: &str, value: Type) {
+        if let Type::Struct { type_name, .. } = &value {
+            self.type_names.insert(type_name.clone());
+        }
+        self.scope.insert(name.to_owned(), value);
+    }
+
+    pub fn define_constant(&mut self, name: &str, value: Type) {
+        self.constants.insert(name.to_owned(), value);
+    }
+
+    pub fn define_function(&mut self, name: &str, signature: Signature) {
+        self.functions.insert(name.to_owned(), signature);
+    }
+
+    /*
+        Makes a function registered by the host known, which takes any number of arguments of any type
+    */
+    pub fn define_native(&mut self, name: &str) {
+        self.natives.insert(name.to_owned());
+    }
+
+    pub fn define_enum(&mut self, name: &str, variants: Vec<(String, usize)>) {
+        self.type_names.insert(name.to_owned());
+        self.enums.insert(name.to_owned(), variants);
+    }
+
+    pub fn check(&mut self, words: &[Word]) -> Result<()> {
+        self.declare_ahead(words);
+        let mut source = Tokens::new(words);
+        self.block(&mut source, &[])
+    }
+
+    /*
+        Functions can call functions defined further down, and annotations can name types declared later,
+        so their names and signatures are gathered before anything is checked
+    */
+    fn declare_ahead(&mut self, words: &[Word]) {
+        let mut source = Tokens::new(words);
+        while let Some(word) = source.next() {
+            match word {
+                "function" => {
+                    if let Some(name) = source.next() {
+                        let signature = types::read_signature(&mut source);
+                        self.functions.insert(name.to_owned(), signature);
+                    }
+                }
+                "method" => {
+                    if let (Some(type_name), Some(name)) = (source.next(), source.next()) {
+                        let signature = types::read_signature(&mut source);
+                        self.functions
+                            .insert(format!("{}.{}", type_name, name), signature);
+                    }
+                }
+                "struct" | "enum" => {
+                    if let Some(name) = source.peek() {
+                        self.type_names.insert(name.to_owned());
+                    }
+                }
+                "string" => {
+                    source.by_ref().find(|&word| word == "endstring");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn block(&mut self, source: &mut Tokens, terminators: &[&str]) -> Result<()> {
+        while let Some(word) = source.peek() {
+            if terminators.contains(&word) {
+                break;
+            }
+            source.next();
+            self.statement(word, source)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, word: &str, source: &mut Tokens) -> Result<()> {
+        let span = source.span();
+        let keyword = Lexeme::parse(word)
+            .ok_or_else(|| NekoError::at(format!("Unknown command: {}", word), span))?;
+        if keyword.assigns() {
+            if let Some(name) = source.peek() {
+                self.check_mutable(name.trim_end_matches(':'), span)?;
+            }
+        }
+        match keyword {
+            Lexeme::Var | Lexeme::Float => {
+                let (name, annotation) = self.declaration(source, span)?;
+                let word = self.word(source, word)?;
+                let value = if keyword == Lexeme::Var && word == "none" {
+                    Type::None
+                } else if keyword == Lexeme::Var
+                    && (lexeme::boolean(word).is_some() || word.parse::<i32>().is_ok())
+                {
+                    Type::Int
+                } else if keyword == Lexeme::Float && word.parse::<f32>().is_ok() {
+                    Type::Float
+                } else {
+                    return Err(NekoError::at(
+                        format!("Invalid value for {}: {}", name, word),
+                        source.span(),
+                    ));
+                };
+                self.declare(name, annotation, value, span)?;
+            }
+            Lexeme::Const => {
+                let (name, annotation) = self.declaration(source, span)?;
+                if self.constants.contains_key(name) {
+                    return Err(NekoError::at(
+                        format!("Constant {} is already defined", name),
+                        source.span(),
+                    ));
+                }
+                let value = self.value(name, source)?;
+                self.check_annotation(name, annotation, &value, span)?;
+                self.constants.insert(name.to_owned(), value);
+            }
+            Lexeme::Array => {
+                let (name, annotation) = self.declaration(source, span)?;
+                self.array(name, word, source)?;
+                self.declare(name, annotation, Type::Array, span)?;
+            }
+            Lexeme::String => {
+                let (name, annotation) = self.declaration(source, span)?;
+                source.by_ref().find(|&word| word == "endstring");
+                self.declare(name, annotation, Type::Str, span)?;
+            }
+            Lexeme::Tuple => {
+                let (name, annotation) = self.declaration(source, span)?;
+                let elements = source.next_tuple().ok_or_else(|| {
+                    NekoError::at(format!("Expected (a, b, ...) after tuple {}", name), span)
+                })?;
+                let tuple = self.tuple(&elements, span)?;
+                self.declare(name, annotation, tuple, span)?;
+            }
+            Lexeme::Map => {
+                let (name, annotation) = self.declaration(source, span)?;
+                while let Some(key) = source.next() {
+                    if key == "endmap" {
+                        break;
+                    }
+                    self.value(key, source)?;
+                }
+                self.declare(name, annotation, Type::Map, span)?;
+            }
+            Lexeme::Struct => {
+                let name = self.word(source, word)?;
+                let instance = self.read_struct(name, source)?;
+                self.scope.insert(name.to_owned(), instance);
+            }
+            Lexeme::Function => {
+                let name = self.word(source, word)?;
+                let signature = types::read_signature(source);
+                let body = source.skip_block(&["end"]);
+                source.next();
+                self.function(&signature, None, body)?;
+                self.functions.insert(name.to_owned(), signature);
+            }
+            Lexeme::Method => {
+                let type_name = self.word(source, word)?;
+                let name = self.word(source, word)?;
+                let signature = types::read_signature(source);
+                let body = source.skip_block(&["end"]);
+                source.next();
+                let receiver = match self.scope.get(type_name) {
+                    Some(instance @ Type::Struct { .. }) => instance.clone(),
+                    _ => Type::Unknown,
+                };
+                self.function(&signature, Some(receiver), body)?;
+                self.functions
+                    .insert(format!("{}.{}", type_name, name), signature);
+            }
+            Lexeme::Enum => {
+                let name = self.word(source, word)?;
+                let mut variants = vec![];
+                let mut fields = vec![];
+                for word in source.by_ref() {
+                    if word == "|" || word == "endenum" {
+                        if fields.is_empty() {
+                            return Err(NekoError::at(
+                                format!("Empty variant in enum {}", name),
+                                source.span(),
+                            ));
+                        }
+                        let variant: String = fields.remove(0);
+                        variants.push((variant, fields.len()));
+                        fields.clear();
+                        if word == "endenum" {
+                            break;
+                        }
+                    } else {
+                        fields.push(word.to_owned());
+                    }
+                }
+                self.define_enum(name, variants);
+            }
+            Lexeme::Match => {
+                let name = self.word(source, word)?;
+                let value = self.operand(name, source.span())?;
+                self.match_cases(name, value, source, span)?;
+            }
+            Lexeme::Switch => {
+                let name = self.word(source, word)?;
+                self.expect(name, "int", source.span())?;
+                self.switches += 1;
+                while let Some(word) = source.next() {
+                    match word {
+                        "endswitch" => break,
+                        "break" => continue,
+                        "case" => {
+                            let value = self.word(source, word)?;
+                            if value.parse::<i32>().is_err() {
+                                return Err(NekoError::at(
+                                    format!("Expected an integer after case, found {}", value),
+                                    source.span(),
+                                ));
+                            }
+                            self.block(source, &["break", "case", "endswitch"])?;
+                        }
+                        _ => {
+                            return Err(NekoError::at(
+                                format!("Expected case in switch, found {}", word),
+                                source.span(),
+                            ))
+                        }
+                    }
+                }
+                self.switches -= 1;
+            }
+            Lexeme::Print => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                match lexeme::unquote(name) {
+                    Some(template) => {
+                        let names = format::names(&template)
+                            .map_err(|error| NekoError::at(error.message, span))?;
+                        for name in names {
+                            self.operand(&name, span)?;
+                        }
+                    }
+                    None => {
+                        self.operand(name, span)?;
+                    }
+                }
+            }
+            Lexeme::Call => self.call(source)?,
+            Lexeme::Return => {
+                let mut values = vec![];
+                for word in source.next_list() {
+                    values.push(self.operand(word, source.span())?);
+                }
+                let value = match values.len() {
+                    0 => None,
+                    1 => values.pop(),
+                    _ => Some(Type::Tuple(values)),
+                };
+                if let (Some(annotation), Some(value)) = (&self.returns, value) {
+                    if !value.fits(annotation) {
+                        return Err(NekoError::at(
+                            format!(
+                                "Expected {} to be returned, found {}",
+                                annotation,
+                                value.name()
+                            ),
+                            span,
+                        ));
+                    }
+                }
+            }
+            Lexeme::If | Lexeme::Loop => {
+                let name = self.word(source, word)?;
+                self.expect_number(name, source.span())?;
+                let comparison = self.word(source, word)?;
+                if !matches!(comparison, "==" | "!=" | "<" | "<=" | ">" | ">=") {
+                    return Err(NekoError::at(
+                        format!("Invalid comparison operator: {}", comparison),
+                        source.span(),
+                    ));
+                }
+                let value = self.word(source, word)?;
+                self.expect_number(value, source.span())?;
+                if keyword == Lexeme::If {
+                    self.block(source, &["else", "end"])?;
+                    if source.peek() == Some("else") {
+                        source.next();
+                        self.block(source, &["end"])?;
+                    }
+                } else {
+                    self.loop_body(source)?;
+                }
+                self.close(source, word)?;
+            }
+            Lexeme::Foreach => {
+                let names: Vec<&str> = source.by_ref().take_while(|&word| word != "in").collect();
+                let name = self.word(source, word)?;
+                let collection = match lexeme::unquote(name) {
+                    Some(_) => Type::Str,
+                    None => self.operand(name, source.span())?,
+                };
+                let (key, item) = match collection {
+                    Type::Array | Type::Tuple(_) => (Type::Int, Type::Unknown),
+                    Type::Str => (Type::Int, Type::Str),
+                    Type::Map | Type::Struct { .. } => (Type::Str, Type::Unknown),
+                    Type::Unknown => (Type::Unknown, Type::Unknown),
+                    other => {
+                        return Err(NekoError::at(
+                            format!("Cannot iterate over {}", other.name()),
+                            source.span(),
+                        ))
+                    }
+                };
+                match names[..] {
+                    [item_name] => self.bind(item_name, item, span)?,
+                    [key_name, item_name] => {
+                        self.bind(key_name, key, span)?;
+                        self.bind(item_name, item, span)?;
+                    }
+                    _ => {
+                        return Err(NekoError::at(
+                            "foreach expects one or two names before in",
+                            source.span(),
+                        ))
+                    }
+                }
+                self.loop_body(source)?;
+                self.close(source, word)?;
+            }
+            Lexeme::Break | Lexeme::Continue => {
+                let in_switch = keyword == Lexeme::Break && self.switches > 0;
+                if self.loops == 0 && !in_switch {
+                    return Err(NekoError::at("break or continue outside of a loop", span));
+                }
+            }
+            Lexeme::Add | Lexeme::Sub | Lexeme::Mul | Lexeme::Div | Lexeme::POW => {
+                let target = self.word(source, word)?;
+                self.expect_variable(target, "int", source.span())?;
+                let operand = self.word(source, word)?;
+                self.expect(operand, "int", source.span())?;
+            }
+            Lexeme::AddF | Lexeme::SubF | Lexeme::MulF | Lexeme::DivF => {
+                let target = self.word(source, word)?;
+                self.expect_variable(target, "float", source.span())?;
+                let operand = self.word(source, word)?;
+                self.expect(operand, "float", source.span())?;
+            }
+            Lexeme::Sqrt | Lexeme::ABS => {
+                let target = self.word(source, word)?;
+                self.expect_variable(target, "int", source.span())?;
+            }
+            Lexeme::Concat
+            | Lexeme::Len
+            | Lexeme::Substr
+            | Lexeme::IndexOf
+            | Lexeme::Split
+            | Lexeme::Join
+            | Lexeme::Replace
+            | Lexeme::Trim
+            | Lexeme::Upper
+            | Lexeme::Lower
+            | Lexeme::StartsWith
+            | Lexeme::EndsWith
+            | Lexeme::Repeat
+            | Lexeme::Sort
+            | Lexeme::SortDesc
+            | Lexeme::Reverse
+            | Lexeme::Contains
+            | Lexeme::BinarySearch
+            | Lexeme::Sum
+            | Lexeme::Min
+            | Lexeme::Max
+            | Lexeme::Avg
+            | Lexeme::Slice
+            | Lexeme::Fill
+            | Lexeme::IsNone
+            | Lexeme::IsSome
+            | Lexeme::UnwrapOr
+            | Lexeme::Get => {
+                let mut arguments = vec![];
+                for _ in 0..builtins::arity(keyword) {
+                    let argument = self.word(source, word)?;
+                    arguments.push(self.operand(argument, source.span())?);
+                }
+                if source.next() != Some("into") {
+                    return Err(NekoError::at(
+                        format!("Expected into after {}", word),
+                        source.span(),
+                    ));
+                }
+                let name = self.word(source, word)?;
+                self.bind(name, builtin_result(keyword, &arguments), source.span())?;
+            }
+            Lexeme::Set => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                let value = self.value(name, source)?;
+                self.bind(name, value, span)?;
+            }
+            Lexeme::Input | Lexeme::InputInt | Lexeme::InputFloat => {
+                // The name is bound to none once the input has ended
+                let name = self.word(source, word)?;
+                self.bind(name, Type::Unknown, source.span())?;
+            }
+            Lexeme::Pop => {
+                let name = self.word(source, word)?;
+                self.expect_variable(name, "array", source.span())?;
+                if source.next() != Some("into") {
+                    return Err(NekoError::at("Expected into after pop", source.span()));
+                }
+                let target = self.word(source, word)?;
+                self.bind(target, Type::Unknown, source.span())?;
+            }
+            Lexeme::End => {}
+            Lexeme::Else | Lexeme::Endstring | Lexeme::Endstruct => {
+                return Err(NekoError::at(format!("Unexpected {}", word), span));
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Checks a function body in a scope of its own, holding only the parameters and `self` for methods
+    */
+    fn function(
+        &mut self,
+        signature: &Signature,
+        receiver: Option<Type>,
+        body: &[Word],
+    ) -> Result<()> {
+        let mut scope = HashMap::new();
+        if let Some(receiver) = receiver {
+            scope.insert("self".to_owned(), receiver);
+        }
+        for parameter in &signature.parameters {
+            let value = match &parameter.annotation {
+                Some(annotation) => self.annotated(annotation, body_span(body))?,
+                None => Type::Unknown,
+            };
+            scope.insert(parameter.name.clone(), value);
+        }
+        if let Some(annotation) = &signature.returns {
+            self.annotated(annotation, body_span(body))?;
+        }
+        let caller_scope = std::mem::replace(&mut self.scope, scope);
+        let caller_returns = std::mem::replace(&mut self.returns, signature.returns.clone());
+        let caller_loops = std::mem::take(&mut self.loops);
+        let caller_switches = std::mem::take(&mut self.switches);
+        let result = self.block(&mut Tokens::new(body), &[]);
+        self.scope = caller_scope;
+        self.returns = caller_returns;
+        self.loops = caller_loops;
+        self.switches = caller_switches;
+        result
+    }
+
+    fn call(&mut self, source: &mut Tokens) -> Result<()> {
+        let target = self.word(source, "call")?;
+        let span = source.span();
+        if self.natives.contains(target) {
+            self.arguments(target, None, source, span)?;
+            return self.results(target, None, source, span);
+        }
+        if let Some((type_name, variant)) = target.split_once('.') {
+            if let Some(variants) = self.enums.get(type_name) {
+                let fields = variants
+                    .iter()
+                    .find(|(name, _)| name == variant)
+                    .map(|&(_, fields)| fields)
+                    .ok_or_else(|| NekoError::at(format!("Unknown variant: {}", target), span))?;
+                self.arguments(target, Some(fields), source, span)?;
+                if source.next() != Some("into") {
+                    return Err(NekoError::at(
+                        format!("Expected into after {}", target),
+                        span,
+                    ));
+                }
+                let name = self.word(source, target)?;
+                return self.bind(name, Type::Enum(type_name.to_owned()), source.span());
+            }
+        }
+        let function_name = match target.rsplit_once('.') {
+            Some((receiver, method)) => match self.operand(receiver, span)? {
+                Type::Struct { type_name, .. } => format!("{}.{}", type_name, method),
+                Type::Unknown => {
+                    let suffix = format!(".{}", method);
+                    let mut methods = self.functions.keys().filter(|name| name.ends_with(&suffix));
+                    match (methods.next(), methods.next()) {
+                        (Some(name), None) => name.clone(),
+                        // The receiver could be of any type with the method, or of none, which only running tells
+                        _ => {
+                            self.arguments(target, None, source, span)?;
+                            return self.results(target, None, source, span);
+                        }
+                    }
+                }
+                other => {
+                    return Err(NekoError::at(
+                        format!("Cannot call {} on {}", method, other.name()),
+                        span,
+                    ))
+                }
+            },
+            None => target.to_owned(),
+        };
+        let signature =
+            self.functions.get(&function_name).cloned().ok_or_else(|| {
+                NekoError::at(format!("Unknown function: {}", function_name), span)
+            })?;
+        let arguments = self.arguments(target, Some(signature.parameters.len()), source, span)?;
+        for (parameter, (argument, span)) in signature.parameters.iter().zip(arguments) {
+            if let Some(annotation) = &parameter.annotation {
+                if !argument.fits(annotation) {
+                    return Err(NekoError::at(
+                        format!(
+                            "Expected {} for {}, found {}",
+                            annotation,
+                            parameter.name,
+                            argument.name()
+                        ),
+                        span,
+                    ));
+                }
+            }
+        }
+        self.results(target, signature.returns.as_deref(), source, span)
+    }
+
+    /*
+        Binds the names after the `into` of a call, if it has one, to the type the function is declared to return
+    */
+    fn results(
+        &mut self,
+        target: &str,
+        returns: Option<&str>,
+        source: &mut Tokens,
+        span: Span,
+    ) -> Result<()> {
+        if source.peek() == Some("into") {
+            source.next();
+            let names = source.next_list();
+            let result = match returns {
+                Some(annotation) => self.annotated(annotation, span)?,
+                None => Type::Unknown,
+            };
+            match (&names[..], result) {
+                ([], _) => {
+                    return Err(NekoError::at(
+                        format!("Expected a name after into in call {}", target),
+                        span,
+                    ))
+                }
+                ([name], result) => self.bind(name, result, span)?,
+                (names, Type::Tuple(values)) if names.len() == values.len() => {
+                    for (name, value) in names.iter().zip(values) {
+                        self.bind(name, value, span)?;
+                    }
+                }
+                (names, _) => {
+                    for name in names {
+                        self.bind(name, Type::Unknown, span)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Reads the arguments of a call, which run up to `into` or the next statement,
+        and checks there are as many as expected when a number is expected
+    */
+    fn arguments(
+        &self,
+        target: &str,
+        expected: Option<usize>,
+        source: &mut Tokens,
+        span: Span,
+    ) -> Result<Vec<(Type, Span)>> {
+        let mut arguments = vec![];
+        while let Some(word) = source.peek() {
+            if word == "into" || lexeme::ends_list(word) {
+                break;
+            }
+            source.next();
+            arguments.push((self.operand(word, source.span())?, source.span()));
+        }
+        if let Some(expected) = expected.filter(|&expected| expected != arguments.len()) {
+            return Err(NekoError::at(
+                format!(
+                    "{} expects {} arguments, found {}",
+                    target,
+                    expected,
+                    arguments.len()
+                ),
+                span,
+            ));
+        }
+        Ok(arguments)
+    }
+
+    fn match_cases(
+        &mut self,
+        name: &str,
+        value: Type,
+        source: &mut Tokens,
+        span: Span,
+    ) -> Result<()> {
+        let mut type_name = match value {
+            Type::Enum(type_name) => Some(type_name),
+            Type::Unknown => None,
+            other => {
+                return Err(NekoError::at(
+                    format!("match expects an enum value: {} is {}", name, other.name()),
+                    span,
+                ))
+            }
+        };
+        let mut covered: Vec<String> = vec![];
+        let mut wildcard = false;
+        while let Some(word) = source.next() {
+            match word {
+                "endmatch" => break,
+                "case" => {
+                    let variant = self.word(source, word)?;
+                    let fields = if variant == "_" {
+                        wildcard = true;
+                        0
+                    } else {
+                        if type_name.is_none() {
+                            type_name = self
+                                .enums
+                                .iter()
+                                .find(|(_, variants)| {
+                                    variants.iter().any(|(name, _)| name == variant)
+                                })
+                                .map(|(type_name, _)| type_name.clone());
+                        }
+                        let variants = type_name
+                            .as_ref()
+                            .and_then(|type_name| self.enums.get(type_name));
+                        match variants
+                            .and_then(|variants| variants.iter().find(|(name, _)| name == variant))
+                        {
+                            Some(&(_, fields)) => fields,
+                            None => {
+                                return Err(NekoError::at(
+                                    format!("Unknown variant in match: {}", variant),
+                                    source.span(),
+                                ))
+                            }
+                        }
+                    };
+                    covered.push(variant.to_owned());
+                    for _ in 0..fields {
+                        let binding = self.word(source, word)?;
+                        self.bind(binding, Type::Unknown, source.span())?;
+                    }
+                    self.block(source, &["case", "endmatch"])?;
+                }
+                _ => {
+                    return Err(NekoError::at(
+                        format!("Expected case in match, found {}", word),
+                        source.span(),
+                    ))
+                }
+            }
+        }
+        if let (Some(type_name), false) = (type_name, wildcard) {
+            let missing: Vec<&str> = self.enums[&type_name]
+                .iter()
+                .map(|(variant, _)| variant.as_str())
+                .filter(|variant| !covered.iter().any(|name| name == variant))
+                .collect();
+            if !missing.is_empty() {
+                return Err(NekoError::at(
+                    format!(
+                        "Match on {} does not cover: {}",
+                        type_name,
+                        missing.join(", ")
+                    ),
+                    span,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, source: &mut Tokens) -> Result<()> {
+        self.loops += 1;
+        let result = self.block(source, &["end"]);
+        self.loops -= 1;
+        result
+    }
+
+    /*
+        Reads the `end` closing a block
+    */
+    fn close(&self, source: &mut Tokens, opener: &str) -> Result<()> {
+        match source.next() {
+            Some("end") => Ok(()),
+            _ => Err(NekoError::at(
+                format!("Missing end after {}", opener),
+                source.span(),
+            )),
+        }
+    }
+
+    /*
+        Reads the struct declared after `struct name`, the same way the interpreter does
+    */
+    fn read_struct(&mut self, name: &str, source: &mut Tokens) -> Result<Type> {
+        let mut type_name = name.to_owned();
+        let mut fields: Vec<(String, Type)> = vec![];
+        if source.peek() == Some("as") {
+            source.next();
+            let prototype = self.word(source, "as")?;
+            if let Some(Type::Struct {
+                fields: inherited, ..
+            }) = self.scope.get(prototype)
+            {
+                fields = inherited.clone();
+            }
+            type_name = prototype.to_owned();
+        }
+        while let Some(field) = source.next() {
+            if field == "endstruct" {
+                break;
+            }
+            let value = self.value(field, source)?;
+            match fields.iter_mut().find(|(name, _)| name == field) {
+                Some((_, existing)) => *existing = value,
+                None => fields.push((field.to_owned(), value)),
+            }
+        }
+        Ok(Type::Struct { type_name, fields })
+    }
+
+    /*
+        The type of a value declared inline, as in a struct field, map entry or constant
+    */
+    fn value(&mut self, name: &str, source: &mut Tokens) -> Result<Type> {
+        let word = self.word(source, name)?;
+        match word {
+            "struct" => self.read_struct(name, source),
+            "string" => {
+                source.by_ref().find(|&word| word == "endstring");
+                Ok(Type::Str)
+            }
+            _ if Lexeme::parse(word) == Some(Lexeme::Array) => {
+                self.array(name, word, source)?;
+                Ok(Type::Array)
+            }
+            _ => self.operand(word, source.span()),
+        }
+    }
+
+    /*
+        Reads `size elements...`, the part of an array declaration after its name,
+        checking the elements against the element type of a keyword such as `array<float>`
+    */
+    fn array(&mut self, name: &str, keyword: &str, source: &mut Tokens) -> Result<()> {
+        let element_type = lexeme::element_type(keyword);
+        if let Some(element_type) = element_type {
+            self.annotated(element_type, source.span())?;
+        }
+        let size = self.word(source, keyword)?;
+        let size: usize = size
+            .parse()
+            .map_err(|_| NekoError::at(format!("Invalid array size: {}", size), source.span()))?;
+        for _ in 0..size {
+            let word = self.word(source, keyword)?;
+            let span = source.span();
+            let element = match word {
+                "struct" => self.read_struct(name, source)?,
+                _ => self.operand(word, span)?,
+            };
+            if let Some(element_type) = element_type {
+                if !element.fits_element(element_type) {
+                    return Err(NekoError::at(
+                        format!(
+                            "Expected {} elements in {}, found {}",
+                            element_type,
+                            name,
+                            element.name()
+                        ),
+                        span,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Reads a name being declared, checking the type it is annotated with exists
+    */
+    fn declaration<'a>(
+        &self,
+        source: &mut Tokens<'a>,
+        span: Span,
+    ) -> Result<(&'a str, Option<&'a str>)> {
+        let (name, annotation) = source
+            .next_name()
+            .ok_or_else(|| NekoError::at("Expected a name", span))?;
+        if let Some(annotation) = annotation {
+            self.annotated(annotation, source.span())?;
+        }
+        Ok((name, annotation))
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        annotation: Option<&str>,
+        value: Type,
+        span: Span,
+    ) -> Result<()> {
+        self.check_annotation(name, annotation, &value, span)?;
+        self.bind(name, value, span)
+    }
+
+    fn check_annotation(
+        &self,
+        name: &str,
+        annotation: Option<&str>,
+        value: &Type,
+        span: Span,
+    ) -> Result<()> {
+        match annotation {
+            Some(annotation) if !value.fits(annotation) => Err(NekoError::at(
+                format!(
+                    "{} is declared {} but given {}",
+                    name,
+                    annotation,
+                    value.name()
+                ),
+                span,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+        The type an annotation names, which must be a builtin type or a declared struct or enum type
+    */
+    fn annotated(&self, annotation: &str, span: Span) -> Result<Type> {
+        if let Some(base) = annotation.strip_suffix('?') {
+            self.annotated(base, span)?;
+            return Ok(Type::Unknown);
+        }
+        if BUILTIN_TYPES.contains(&annotation) {
+            return Ok(types::from_annotation(annotation));
+        }
+        if self.enums.contains_key(annotation) {
+            return Ok(Type::Enum(annotation.to_owned()));
+        }
+        if let Some(instance @ Type::Struct { .. }) = self.scope.get(annotation) {
+            return Ok(instance.clone());
+        }
+        if self.type_names.contains(annotation) {
+            return Ok(Type::Unknown);
+        }
+        Err(NekoError::at(format!("Unknown type: {}", annotation), span))
+    }
+
+    fn bind(&mut self, name: &str, value: Type, span: Span) -> Result<()> {
+        self.check_mutable(name, span)?;
+        if name.contains('.') {
+            if self.lookup(name).is_none() {
+                return Err(NekoError::at(format!("Unknown field: {}", name), span));
+            }
+            return Ok(());
+        }
+        self.scope.insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    fn check_mutable(&self, name: &str, span: Span) -> Result<()> {
+        let root = name.split('.').next().unwrap_or(name);
+        if self.constants.contains_key(root) {
+            return Err(NekoError::at(
+                format!("Cannot assign to constant {}", root),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /*
+        Checks the target of an arithmetic statement is a variable holding the expected type
+    */
+    fn expect_variable(&self, name: &str, expected: &str, span: Span) -> Result<()> {
+        let root = name.split('.').next().unwrap_or(name);
+        if !self.scope.contains_key(root) {
+            return Err(NekoError::at(format!("Unknown variable: {}", root), span));
+        }
+        self.expect(name, expected, span)
+    }
+
+    fn expect_number(&self, word: &str, span: Span) -> Result<()> {
+        let value = self.operand(word, span)?;
+        if !value.fits("int") && !value.fits("float") {
+            return Err(NekoError::at(
+                format!("Expected a number but {} is {}", word, value.name()),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    fn expect(&self, word: &str, expected: &str, span: Span) -> Result<()> {
+        let value = self.operand(word, span)?;
+        if !value.fits(expected) {
+            return Err(NekoError::at(
+                format!("Expected {} but {} is {}", expected, word, value.name()),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /*
+        The type of a tuple literal read by `next_tuple`
+    */
+    fn tuple(&self, elements: &[TupleElement], span: Span) -> Result<Type> {
+        let types = elements
+            .iter()
+            .map(|element| match element {
+                TupleElement::Word(word) => self.operand(word, span),
+                TupleElement::Tuple(elements) => self.tuple(elements, span),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Type::Tuple(types))
+    }
+
+    /*
+        The type of an operand: a literal, or the name of a binding, constant or field
+    */
+    fn operand(&self, word: &str, span: Span) -> Result<Type> {
+        if lexeme::unquote(word).is_some() {
+            Ok(Type::Str)
+        } else if word == "none" {
+            Ok(Type::None)
+        } else if lexeme::boolean(word).is_some() || word.parse::<i32>().is_ok() {
+            Ok(Type::Int)
+        } else if word.parse::<f32>().is_ok() {
+            Ok(Type::Float)
+        } else {
+            self.lookup(word).ok_or_else(|| {
+                let root = word.split('.').next().unwrap_or(word);
+                match self.lookup(root) {
+                    Some(_) if root != word => {
+                        NekoError::at(format!("Unknown field: {}", word), span)
+                    }
+                    _ => NekoError::at(format!("Unknown value: {}", word), span),
+                }
+            })
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        let mut parts = name.split('.');
+        let root = parts.next()?;
+        let mut value = self
+            .scope
+            .get(root)
+            .or_else(|| self.constants.get(root))?
+            .clone();
+        for part in parts {
+            value = value.field(part)?;
+        }
+        Some(value)
+    }
+
+    fn word<'a>(&self, source: &mut Tokens<'a>, after: &str) -> Result<&'a str> {
+        source.next().ok_or_else(|| {
+            NekoError::at(
+                format!("Unexpected end of program after {}", after),
+                source.span(),
+            )
+        })
+    }
+}
+
+/*
+    Checks a program on its own, as `neko check` does
+*/
+pub fn check(source_code: &str) -> Result<()> {
+    Checker::new().check(&lexeme::tokenize(source_code))
+}
+
+fn body_span(body: &[Word]) -> Span {
+    body.first()
+        .map_or(Span { line: 1, column: 1 }, |word| word.span)
+}
+
+/*
+    The type a builtin stores into the name after `into`
+*/
+fn builtin_result(keyword: Lexeme, arguments: &[Type]) -> Type {
+    match keyword {
+        Lexeme::Len
+        | Lexeme::IndexOf
+        | Lexeme::Contains
+        | Lexeme::BinarySearch
+        | Lexeme::StartsWith
+        | Lexeme::EndsWith
+        | Lexeme::IsNone
+        | Lexeme::IsSome => Type::Int,
+        Lexeme::Split | Lexeme::Slice | Lexeme::Fill => Type::Array,
+        Lexeme::Substr
+        | Lexeme::Join
+        | Lexeme::Replace
+        | Lexeme::Trim
+        | Lexeme::Upper
+        | Lexeme::Lower
+        | Lexeme::Repeat => Type::Str,
+        Lexeme::Concat | Lexeme::Sort | Lexeme::SortDesc | Lexeme::Reverse => {
+            arguments.first().cloned().unwrap_or(Type::Unknown)
+        }
+        Lexeme::UnwrapOr => match &arguments[0] {
+            Type::None | Type::Unknown => arguments[1].clone(),
+            value => value.clone(),
+        },
+        _ => Type::Unknown,
+    }
+}
@@ -0,0 +1,1020 @@
+use crate::builtins;
+use crate::bytecode::{
+    Arithmetic, ArithmeticSite, CallSite, Chunk, Iteration, Layout, MatchCase, MatchTable, Op,
+    Operand, Place, Slots, SwitchTable,
+};
+use crate::error::{NekoError, Result};
+use crate::format;
+use crate::interpreter::{Function, Variant};
+use crate::lexeme::{self, Comparison, Lexeme, Span, Tokens, TupleElement, Word};
+use crate::types::{self, Signature};
+use crate::value::Value;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/*
+    Where a `break` or `continue` inside a loop or switch jumps to. `depth` is how many values the
+    enclosing statements keep on the stack at that point, so a jump from deeper down pops the rest first.
+    Jumps to the end of the statement are patched once its code is complete.
+*/
+struct Target {
+    continues_at: Option<usize>,
+    breaks: Vec<usize>,
+    depth: usize,
+}
+
+/*
+    Translates the words of a program into a chunk of instructions for the stack machine, reading
+    statements the way the tree-walking interpreter does. Blocks become jumps, so nothing is scanned
+    or skipped while the program runs.
+
+    Every plain name is resolved to a slot of the scope the code runs in. Field paths and constants,
+    which live outside the scope, are still looked up by name. A name that is read but bound nowhere
+    in the scope, and is not a constant, fails the compilation.
+*/
+pub struct Compiler {
+    chunk: Chunk,
+    targets: Vec<Target>,
+    depth: usize,
+    in_function: bool,
+    slots: Slots,
+    constants: HashSet<String>,
+    bound: HashSet<String>,
+    reads: Vec<(String, Span)>,
+}
+
+impl Compiler {
+    fn new(in_function: bool, slots: Slots, constants: &HashSet<String>, words: &[Word]) -> Self {
+        let mut constants = constants.clone();
+        constants.extend(declared_constants(words));
+        Compiler {
+            chunk: Chunk::default(),
+            targets: vec![],
+            depth: 0,
+            in_function,
+            bound: slots.names().iter().cloned().collect(),
+            slots,
+            constants,
+            reads: vec![],
+        }
+    }
+
+    /*
+        Compiles a program to run in the global scope, whose names keep the slots `globals` gave them.
+        `constants` are the constants defined before the program. A `break` or `continue` outside of
+        any loop fails when it is reached.
+    */
+    pub fn compile(words: &[Word], globals: &Slots, constants: &HashSet<String>) -> Result<Chunk> {
+        let mut compiler = Compiler::new(false, globals.clone(), constants, words);
+        compiler.block(&mut Tokens::new(words), &[])?;
+        compiler.finish()
+    }
+
+    /*
+        Compiles the body of a function to run in a scope of its own, which starts with `self` for a method
+        and the parameters. A `break` or `continue` outside of any loop leaves the function, for the caller to report.
+    */
+    pub fn compile_function(
+        words: &[Word],
+        signature: &Signature,
+        method: bool,
+        constants: &HashSet<String>,
+    ) -> Result<Chunk> {
+        let mut slots = Slots::default();
+        if method {
+            slots.resolve("self");
+        }
+        for parameter in &signature.parameters {
+            slots.resolve(&parameter.name);
+        }
+        let mut compiler = Compiler::new(true, slots, constants, words);
+        compiler.block(&mut Tokens::new(words), &[])?;
+        compiler.finish()
+    }
+
+    fn finish(mut self) -> Result<Chunk> {
+        let unknown = self
+            .reads
+            .iter()
+            .find(|(name, _)| !self.bound.contains(name) && !self.constants.contains(name));
+        if let Some((name, span)) = unknown {
+            return Err(NekoError::at(format!("Unknown value: {}", name), *span));
+        }
+        self.chunk.slots = Rc::new(self.slots);
+        Ok(self.chunk)
+    }
+
+    /*
+        Compiles statements until one of `terminators` is reached, leaving it unconsumed
+    */
+    fn block(&mut self, source: &mut Tokens, terminators: &[&str]) -> Result<()> {
+        while let Some(word) = source.peek() {
+            if terminators.contains(&word) {
+                break;
+            }
+            source.next();
+            self.statement(word, source)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, word: &str, source: &mut Tokens) -> Result<()> {
+        let span = source.span();
+        let keyword = Lexeme::parse(word)
+            .ok_or_else(|| NekoError::at(format!("Unknown command: {}", word), span))?;
+        match keyword {
+            Lexeme::Var => {
+                let (name, annotation) = self.name(source, word)?;
+                let literal = self.word(source, word)?;
+                let value = match literal {
+                    "none" => Value::None,
+                    _ => match lexeme::boolean(literal) {
+                        Some(value) => Value::Int(value),
+                        None => Value::Int(literal.parse().map_err(|_| {
+                            NekoError::at(
+                                format!("Expected an integer, found {}", literal),
+                                source.span(),
+                            )
+                        })?),
+                    },
+                };
+                let span = source.span();
+                let constant = self.constant(value);
+                self.emit(Op::Push(Operand::Constant(constant)), span);
+                self.annotate(name, annotation, span);
+                let place = self.place(name, span);
+                self.emit(Op::Store(place), span);
+            }
+            Lexeme::Set => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                self.value(name, source)?;
+                let place = self.place(name, span);
+                self.emit(Op::Store(place), span);
+            }
+            Lexeme::Const => {
+                let (name, annotation) = self.name(source, word)?;
+                let span = source.span();
+                self.value(name, source)?;
+                self.annotate(name, annotation, span);
+                let name = self.intern(name);
+                self.emit(Op::DefineConstant(name), span);
+            }
+            Lexeme::Array => {
+                let (name, annotation) = self.name(source, word)?;
+                let span = source.span();
+                self.array(name, word, source)?;
+                self.annotate(name, annotation, span);
+                self.store_local(name, span);
+            }
+            Lexeme::String => {
+                let (name, annotation) = self.name(source, word)?;
+                let span = source.span();
+                let words: Vec<&str> = source
+                    .by_ref()
+                    .take_while(|&word| word != "endstring")
+                    .collect();
+                let constant = self.constant(Value::Str(words.join(" ")));
+                self.emit(Op::Push(Operand::Constant(constant)), span);
+                self.annotate(name, annotation, span);
+                self.store_local(name, span);
+            }
+            Lexeme::Float => {
+                let (name, annotation) = self.name(source, word)?;
+                let literal = self.word(source, word)?;
+                let value: f32 = literal.parse().map_err(|_| {
+                    NekoError::at(
+                        format!("Expected a float, found {}", literal),
+                        source.span(),
+                    )
+                })?;
+                let span = source.span();
+                let constant = self.constant(Value::Float(value));
+                self.emit(Op::Push(Operand::Constant(constant)), span);
+                self.annotate(name, annotation, span);
+                self.store_local(name, span);
+            }
+            Lexeme::Tuple => {
+                let (name, annotation) = self.name(source, word)?;
+                let elements = source.next_tuple();
+                let span = source.span();
+                let elements = elements.ok_or_else(|| {
+                    NekoError::at(format!("Expected (a, b, ...) after tuple {}", name), span)
+                })?;
+                self.tuple(&elements, span);
+                self.annotate(name, annotation, span);
+                self.store_local(name, span);
+            }
+            Lexeme::Function => {
+                let name = self.word(source, word)?.to_owned();
+                self.function(name, source, span)?;
+            }
+            Lexeme::Method => {
+                let type_name = self.word(source, word)?;
+                let name = self.word(source, word)?;
+                self.function(format!("{}.{}", type_name, name), source, span)?;
+            }
+            Lexeme::Struct => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                self.structure(name, source)?;
+                self.store_local(name, span);
+            }
+            Lexeme::Enum => {
+                let name = self.word(source, word)?;
+                let mut variants = vec![];
+                let mut words = vec![];
+                for word in source.by_ref() {
+                    if word == "|" || word == "endenum" {
+                        let mut words = std::mem::take(&mut words).into_iter();
+                        let variant = words.next().ok_or_else(|| {
+                            NekoError::at(format!("Empty variant in enum {}", name), span)
+                        })?;
+                        variants.push(Variant {
+                            name: variant,
+                            fields: words.collect(),
+                        });
+                        if word == "endenum" {
+                            break;
+                        }
+                    } else {
+                        words.push(word.to_owned());
+                    }
+                }
+                self.chunk.enums.push((name.to_owned(), variants));
+                self.emit(Op::DefineEnum(self.chunk.enums.len() - 1), span);
+            }
+            Lexeme::Match => self.match_cases(source)?,
+            Lexeme::Switch => self.switch(source)?,
+            Lexeme::Print => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                match lexeme::unquote(name) {
+                    Some(template) => {
+                        for name in format::names(&template).unwrap_or_default() {
+                            self.read(&name, span);
+                        }
+                        let template = self.constant(Value::Str(template));
+                        self.emit(Op::PrintFormat(template), span);
+                    }
+                    None => {
+                        self.read(name, span);
+                        let name = self.intern(name);
+                        self.emit(Op::Print(name), span);
+                    }
+                }
+            }
+            Lexeme::Call => self.call(source)?,
+            Lexeme::Return => {
+                let words = source.next_list();
+                for word in &words {
+                    let operand = self.operand(word, source.span());
+                    self.emit(Op::Push(operand), source.span());
+                }
+                self.emit(Op::Return(words.len()), source.span());
+            }
+            Lexeme::If => {
+                let comparison = self.condition(source, word)?;
+                let span = source.span();
+                let skip = self.emit(
+                    Op::JumpUnless {
+                        comparison,
+                        target: 0,
+                    },
+                    span,
+                );
+                self.block(source, &["else", "end"])?;
+                if source.peek() == Some("else") {
+                    source.next();
+                    let end = self.emit(Op::Jump(0), span);
+                    self.patch(skip);
+                    self.block(source, &["end"])?;
+                    self.patch(end);
+                } else {
+                    self.patch(skip);
+                }
+                source.next();
+            }
+            Lexeme::Loop => self.repeat(source, word)?,
+            Lexeme::Foreach => self.foreach(source)?,
+            Lexeme::Break => self.jump_out(false, span),
+            Lexeme::Continue => self.jump_out(true, span),
+            Lexeme::Map => {
+                let (name, annotation) = self.name(source, word)?;
+                let span = source.span();
+                let mut keys = vec![];
+                while let Some(key) = source.next() {
+                    if key == "endmap" {
+                        break;
+                    }
+                    self.value(key, source)?;
+                    keys.push(lexeme::unquote(key).unwrap_or_else(|| key.to_owned()));
+                }
+                let layout = self.layout(name.to_owned(), None, keys);
+                self.emit(Op::MakeMap(layout), span);
+                self.annotate(name, annotation, source.span());
+                self.store_local(name, source.span());
+            }
+            Lexeme::Add => self.arithmetic(source, word, Arithmetic::Add)?,
+            Lexeme::Sub => self.arithmetic(source, word, Arithmetic::Sub)?,
+            Lexeme::Mul => self.arithmetic(source, word, Arithmetic::Mul)?,
+            Lexeme::Div => self.arithmetic(source, word, Arithmetic::Div)?,
+            Lexeme::POW => self.arithmetic(source, word, Arithmetic::Pow)?,
+            Lexeme::AddF => self.arithmetic(source, word, Arithmetic::AddF)?,
+            Lexeme::SubF => self.arithmetic(source, word, Arithmetic::SubF)?,
+            Lexeme::MulF => self.arithmetic(source, word, Arithmetic::MulF)?,
+            Lexeme::DivF => self.arithmetic(source, word, Arithmetic::DivF)?,
+            Lexeme::Sqrt | Lexeme::ABS => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                let place = self.target(name, span);
+                self.emit(Op::Int(place.into()), span);
+                let op = match keyword {
+                    Lexeme::Sqrt => Op::SqrtInt,
+                    _ => Op::AbsInt,
+                };
+                self.emit(op, span);
+                self.emit(Op::Store(place), span);
+            }
+            Lexeme::Concat
+            | Lexeme::Len
+            | Lexeme::Substr
+            | Lexeme::IndexOf
+            | Lexeme::Split
+            | Lexeme::Join
+            | Lexeme::Replace
+            | Lexeme::Trim
+            | Lexeme::Upper
+            | Lexeme::Lower
+            | Lexeme::StartsWith
+            | Lexeme::EndsWith
+            | Lexeme::Repeat
+            | Lexeme::Sort
+            | Lexeme::SortDesc
+            | Lexeme::Reverse
+            | Lexeme::Contains
+            | Lexeme::BinarySearch
+            | Lexeme::Sum
+            | Lexeme::Min
+            | Lexeme::Max
+            | Lexeme::Avg
+            | Lexeme::Slice
+            | Lexeme::Fill
+            | Lexeme::IsNone
+            | Lexeme::IsSome
+            | Lexeme::UnwrapOr
+            | Lexeme::Get => {
+                let mut spans = vec![];
+                for _ in 0..builtins::arity(keyword) {
+                    let argument = self.word(source, word)?;
+                    let operand = self.operand(argument, source.span());
+                    self.emit(Op::Push(operand), source.span());
+                    spans.push(source.span());
+                }
+                self.chunk.builtins.push(spans);
+                let call = self.chunk.builtins.len() - 1;
+                self.emit(Op::Builtin { keyword, call }, span);
+                self.into(source, word)?;
+            }
+            Lexeme::Input | Lexeme::InputInt | Lexeme::InputFloat => {
+                let name = self.word(source, word)?;
+                let span = source.span();
+                self.emit(Op::Input(keyword), span);
+                let place = self.place(name, span);
+                self.emit(Op::Store(place), span);
+            }
+            Lexeme::Pop => {
+                let name = self.word(source, word)?;
+                self.read(name, source.span());
+                let name = self.intern(name);
+                self.emit(Op::PopArray(name), source.span());
+                self.into(source, word)?;
+            }
+            Lexeme::End => {}
+            Lexeme::Else | Lexeme::Endstring | Lexeme::Endstruct => {
+                return Err(NekoError::at(format!("Unexpected {}", word), span))
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        `add x y` and the like, storing the result in the first operand
+    */
+    fn arithmetic(
+        &mut self,
+        source: &mut Tokens,
+        keyword: &str,
+        operation: Arithmetic,
+    ) -> Result<()> {
+        let name = self.word(source, keyword)?;
+        let span = source.span();
+        let target = self.target(name, span);
+        let operand = self.word(source, keyword)?;
+        let site = ArithmeticSite {
+            operation,
+            target,
+            operand: self.operand(operand, source.span()),
+            operand_span: source.span(),
+        };
+        self.chunk.arithmetic.push(site);
+        self.emit(Op::Arithmetic(self.chunk.arithmetic.len() - 1), span);
+        Ok(())
+    }
+
+    /*
+        Reads `a < b` after `if` or `loop`, pushing `b` and then `a`
+    */
+    fn condition(&mut self, source: &mut Tokens, keyword: &str) -> Result<Comparison> {
+        let name = self.word(source, keyword)?;
+        let span = source.span();
+        let comparison = Comparison::from_str(self.word(source, keyword)?);
+        let bound = self.word(source, keyword)?;
+        let bound = self.operand(bound, source.span());
+        self.emit(Op::Number(bound), source.span());
+        let name = self.operand(name, span);
+        self.emit(Op::Number(name), span);
+        Ok(comparison)
+    }
+
+    /*
+        `loop a < b ... end`. The bound `b` is read once and kept on the stack while `a` is read on every pass.
+        Once the condition fails the body runs one last time, as loops always have, and a `break` or `continue`
+        in that last pass belongs to the enclosing loop.
+    */
+    fn repeat(&mut self, source: &mut Tokens, keyword: &str) -> Result<()> {
+        let name = self.word(source, keyword)?;
+        let span = source.span();
+        let comparison = Comparison::from_str(self.word(source, keyword)?);
+        let bound = self.word(source, keyword)?;
+        let bound = self.operand(bound, source.span());
+        self.emit(Op::Number(bound), source.span());
+        self.depth += 1;
+        let operand = self.operand(name, span);
+        let start = self.emit(
+            Op::LoopUnless {
+                comparison,
+                operand,
+                target: 0,
+            },
+            span,
+        );
+        let body = source.clone();
+        self.targets.push(Target {
+            continues_at: Some(start),
+            breaks: vec![],
+            depth: self.depth,
+        });
+        self.block(source, &["end"])?;
+        self.emit(Op::Jump(start), span);
+        let target = self.targets.pop().unwrap();
+        for jump in target.breaks {
+            self.patch(jump);
+        }
+        self.depth -= 1;
+        self.emit(Op::Pop, span);
+        let done = self.emit(Op::Jump(0), span);
+
+        self.patch(start);
+        self.emit(Op::Pop, span);
+        *source = body;
+        self.block(source, &["end"])?;
+        self.patch(done);
+        source.next();
+        Ok(())
+    }
+
+    /*
+        `foreach item in collection ... end`. The entries to walk and the position reached
+        stay on the stack until the loop is left.
+    */
+    fn foreach(&mut self, source: &mut Tokens) -> Result<()> {
+        let names: Vec<&str> = source.by_ref().take_while(|&word| word != "in").collect();
+        let collection = self.word(source, "foreach")?;
+        let span = source.span();
+        let (key, item) = match names[..] {
+            [item] => (None, item),
+            [key, item] => (Some(key), item),
+            _ => {
+                return Err(NekoError::at(
+                    "foreach expects one or two names before in",
+                    span,
+                ))
+            }
+        };
+        let collection = self.operand(collection, span);
+        self.emit(Op::Iterate(collection), span);
+        self.depth += 2;
+        let key = key.map(|key| self.place(key, span));
+        let item = self.place(item, span);
+        self.chunk.iterations.push(Iteration { key, item, exit: 0 });
+        let iteration = self.chunk.iterations.len() - 1;
+        let start = self.emit(Op::Next(iteration), span);
+        self.targets.push(Target {
+            continues_at: Some(start),
+            breaks: vec![],
+            depth: self.depth,
+        });
+        self.block(source, &["end"])?;
+        source.next();
+        self.emit(Op::Jump(start), span);
+        let target = self.targets.pop().unwrap();
+        let exit = self.chunk.code.len();
+        self.chunk.iterations[iteration].exit = exit;
+        for jump in target.breaks {
+            self.patch(jump);
+        }
+        self.depth -= 2;
+        self.emit(Op::Pop, span);
+        self.emit(Op::Pop, span);
+        Ok(())
+    }
+
+    /*
+        `switch name case value ... endswitch`, where a case runs up to the next `break`, `case` or `endswitch`
+        and a `break` inside it leaves the switch
+    */
+    fn switch(&mut self, source: &mut Tokens) -> Result<()> {
+        let name = self.word(source, "switch")?;
+        let span = source.span();
+        let operand = self.operand(name, span);
+        self.emit(Op::Int(operand), span);
+        self.chunk.switches.push(SwitchTable {
+            cases: vec![],
+            end: 0,
+        });
+        let table = self.chunk.switches.len() - 1;
+        self.emit(Op::Switch(table), span);
+        self.targets.push(Target {
+            continues_at: None,
+            breaks: vec![],
+            depth: self.depth,
+        });
+        while let Some(word) = source.next() {
+            match word {
+                "endswitch" => break,
+                "break" => continue,
+                "case" => {
+                    let literal = self.word(source, word)?;
+                    let value = literal.parse().map_err(|_| {
+                        NekoError::at(
+                            format!("Expected an integer after case, found {}", literal),
+                            source.span(),
+                        )
+                    })?;
+                    let start = self.chunk.code.len();
+                    self.chunk.switches[table].cases.push((value, start));
+                    self.block(source, &["break", "case", "endswitch"])?;
+                    let end = self.emit(Op::Jump(0), span);
+                    self.targets.last_mut().unwrap().breaks.push(end);
+                }
+                _ => {
+                    return Err(NekoError::at(
+                        format!("Expected case in switch, found {}", word),
+                        source.span(),
+                    ))
+                }
+            }
+        }
+        let target = self.targets.pop().unwrap();
+        self.chunk.switches[table].end = self.chunk.code.len();
+        for jump in target.breaks {
+            self.patch(jump);
+        }
+        Ok(())
+    }
+
+    /*
+        `match name case Variant bindings... code... endmatch`. The case to run is picked when the match is reached,
+        so each case only needs a jump to the end.
+    */
+    fn match_cases(&mut self, source: &mut Tokens) -> Result<()> {
+        let name = self.word(source, "match")?;
+        let span = source.span();
+        self.read(name, span);
+        self.chunk.matches.push(MatchTable {
+            name: name.to_owned(),
+            cases: vec![],
+        });
+        let table = self.chunk.matches.len() - 1;
+        self.emit(Op::Match(table), span);
+        let mut ends = vec![];
+        while let Some(word) = source.next() {
+            match word {
+                "endmatch" => break,
+                "case" => {
+                    let variant = self.word(source, word)?.to_owned();
+                    let bindings = source
+                        .next_list()
+                        .into_iter()
+                        .map(|binding| self.place(binding, source.span()))
+                        .collect();
+                    let target = self.chunk.code.len();
+                    self.chunk.matches[table].cases.push(MatchCase {
+                        variant,
+                        bindings,
+                        target,
+                    });
+                    self.block(source, &["case", "endmatch"])?;
+                    ends.push(self.emit(Op::Jump(0), span));
+                }
+                _ => {
+                    return Err(NekoError::at(
+                        format!("Expected case in match, found {}", word),
+                        source.span(),
+                    ))
+                }
+            }
+        }
+        for end in ends {
+            self.patch(end);
+        }
+        Ok(())
+    }
+
+    /*
+        `call target arguments... into names...`. Whether the target is a function, a method or an enum variant
+        is decided when the call runs, since they can be defined by code that has not run yet.
+    */
+    fn call(&mut self, source: &mut Tokens) -> Result<()> {
+        let target = self.word(source, "call")?;
+        let span = source.span();
+        let mut arguments = vec![];
+        while let Some(word) = source.peek() {
+            if word == "into" || lexeme::ends_list(word) {
+                break;
+            }
+            source.next();
+            let operand = self.operand(word, source.span());
+            self.emit(Op::Push(operand), source.span());
+            arguments.push(source.span());
+        }
+        let mut into = vec![];
+        if source.peek() == Some("into") {
+            source.next();
+            into = source
+                .next_list()
+                .into_iter()
+                .map(|name| self.place(name, source.span()))
+                .collect();
+        }
+        self.chunk.calls.push(CallSite {
+            target: target.to_owned(),
+            arguments,
+            into,
+            into_span: source.span(),
+        });
+        self.emit(Op::Call(self.chunk.calls.len() - 1), span);
+        Ok(())
+    }
+
+    /*
+        Compiles the body of a function or method into a chunk of its own, to be defined when this point is reached
+    */
+    fn function(&mut self, name: String, source: &mut Tokens, span: Span) -> Result<()> {
+        let signature = types::read_signature(source);
+        let code = source.skip_block(&["end"]).to_vec();
+        source.next();
+        let method = name.contains('.');
+        let chunk = Compiler::compile_function(&code, &signature, method, &self.constants)?;
+        let function = Function {
+            signature,
+            code,
+            chunk: Rc::new(chunk),
+        };
+        self.chunk.functions.push((name, function));
+        self.emit(Op::DefineFunction(self.chunk.functions.len() - 1), span);
+        Ok(())
+    }
+
+    /*
+        A `break` jumps past the innermost loop or switch and a `continue` back to the start of the innermost loop,
+        popping what the statements in between keep on the stack
+    */
+    fn jump_out(&mut self, continues: bool, span: Span) {
+        let depth = self.depth;
+        let found = self
+            .targets
+            .iter()
+            .rposition(|target| !continues || target.continues_at.is_some());
+        let Some(index) = found else {
+            let op = match (self.in_function, continues) {
+                (false, _) => Op::OutsideLoop,
+                (true, false) => Op::Break,
+                (true, true) => Op::Continue,
+            };
+            self.emit(op, span);
+            return;
+        };
+        for _ in self.targets[index].depth..depth {
+            self.emit(Op::Pop, span);
+        }
+        match self.targets[index].continues_at {
+            Some(start) if continues => {
+                self.emit(Op::Jump(start), span);
+            }
+            _ => {
+                let jump = self.emit(Op::Jump(0), span);
+                self.targets[index].breaks.push(jump);
+            }
+        }
+    }
+
+    /*
+        Pushes the value declared after `name` in a `set`, `const`, struct field or map entry: an inline
+        `struct ... endstruct`, `string ... endstring` or array, or an operand
+    */
+    fn value(&mut self, name: &str, source: &mut Tokens) -> Result<()> {
+        let word = self.word(source, name)?;
+        let span = source.span();
+        match word {
+            "struct" => self.structure(name, source)?,
+            "string" => {
+                let words: Vec<&str> = source
+                    .by_ref()
+                    .take_while(|&word| word != "endstring")
+                    .collect();
+                let constant = self.constant(Value::Str(words.join(" ")));
+                self.emit(Op::Push(Operand::Constant(constant)), span);
+            }
+            _ if Lexeme::parse(word) == Some(Lexeme::Array) => self.array(name, word, source)?,
+            _ => {
+                let operand = self.operand(word, span);
+                self.emit(Op::Push(operand), span);
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Pushes the struct declared after `struct name`, up to the closing `endstruct`
+    */
+    fn structure(&mut self, name: &str, source: &mut Tokens) -> Result<()> {
+        let span = source.span();
+        let mut type_name = name.to_owned();
+        let mut prototype = None;
+        if source.peek() == Some("as") {
+            source.next();
+            let name = self.word(source, "as")?;
+            type_name = name.to_owned();
+            prototype = Some(name.to_owned());
+        }
+        let mut fields = vec![];
+        while let Some(field) = source.next() {
+            if field == "endstruct" {
+                break;
+            }
+            self.value(field, source)?;
+            fields.push(field.to_owned());
+        }
+        let layout = self.layout(type_name, prototype, fields);
+        self.emit(Op::MakeStruct(layout), span);
+        Ok(())
+    }
+
+    /*
+        Pushes the array declared by `size elements...`, checking each element against the element type
+        of a keyword such as `array<float>`
+    */
+    fn array(&mut self, name: &str, keyword: &str, source: &mut Tokens) -> Result<()> {
+        let literal = self.word(source, keyword)?;
+        let size: usize = literal.parse().map_err(|_| {
+            NekoError::at(
+                format!("Expected the size of {}, found {}", name, literal),
+                source.span(),
+            )
+        })?;
+        let element_type =
+            lexeme::element_type(keyword).map(|element_type| self.intern(element_type));
+        let array = self.intern(name);
+        for _ in 0..size {
+            let word = self.word(source, keyword)?;
+            let span = source.span();
+            match word {
+                "struct" => self.structure(name, source)?,
+                _ => {
+                    let operand = self.operand(word, span);
+                    self.emit(Op::Push(operand), span);
+                }
+            }
+            if let Some(element_type) = element_type {
+                self.emit(
+                    Op::Element {
+                        array,
+                        element_type,
+                    },
+                    span,
+                );
+            }
+        }
+        self.emit(Op::MakeArray(size), source.span());
+        Ok(())
+    }
+
+    /*
+        Reads `into name` after a builtin or `pop`, storing the value on the stack in it
+    */
+    fn into(&mut self, source: &mut Tokens, keyword: &str) -> Result<()> {
+        if source.next() != Some("into") {
+            return Err(NekoError::at(
+                format!("Expected into after {}", keyword),
+                source.span(),
+            ));
+        }
+        let name = self.word(source, keyword)?;
+        let place = self.place(name, source.span());
+        self.emit(Op::Store(place), source.span());
+        Ok(())
+    }
+
+    fn annotate(&mut self, name: &str, annotation: Option<&str>, span: Span) {
+        if let Some(annotation) = annotation {
+            let name = self.intern(name);
+            let annotation = self.intern(annotation);
+            self.emit(Op::Annotate { name, annotation }, span);
+        }
+    }
+
+    fn store_local(&mut self, name: &str, span: Span) {
+        let place = self.place(name, span);
+        self.emit(Op::StoreLocal(place), span);
+    }
+
+    /*
+        Pushes the elements of a tuple literal read by `next_tuple` and builds the tuple from them
+    */
+    fn tuple(&mut self, elements: &[TupleElement], span: Span) {
+        for element in elements {
+            match element {
+                TupleElement::Word(word) => {
+                    let operand = self.operand(word, span);
+                    self.emit(Op::Push(operand), span);
+                }
+                TupleElement::Tuple(elements) => self.tuple(elements, span),
+            }
+        }
+        self.emit(Op::MakeTuple(elements.len()), span);
+    }
+
+    /*
+        An operand is a literal, which goes to the constant pool, or the name of a binding or field
+    */
+    fn operand(&mut self, word: &str, span: Span) -> Operand {
+        let literal = if let Some(text) = lexeme::unquote(word) {
+            Value::Str(text)
+        } else if word == "none" {
+            Value::None
+        } else if let Some(value) = lexeme::boolean(word) {
+            Value::Int(value)
+        } else if let Ok(value) = word.parse() {
+            Value::Int(value)
+        } else if let Ok(value) = word.parse() {
+            Value::Float(value)
+        } else {
+            self.read(word, span);
+            if word.contains('.') || self.constants.contains(word) {
+                return Operand::Name(self.intern(word));
+            }
+            return Operand::Slot(self.slots.resolve(word));
+        };
+        Operand::Constant(self.constant(literal))
+    }
+
+    /*
+        Where a value bound to `name` goes. A plain name is bound in its slot, while storing into a field
+        path needs the struct it starts from to exist.
+    */
+    fn place(&mut self, name: &str, span: Span) -> Place {
+        if name.contains('.') {
+            self.read(name, span);
+            return Place::Name(self.intern(name));
+        }
+        if self.constants.contains(name) {
+            return Place::Name(self.intern(name));
+        }
+        self.bound.insert(name.to_owned());
+        Place::Slot(self.slots.resolve(name))
+    }
+
+    /*
+        The place of a name a statement changes rather than binds, such as the target of `add`,
+        which has to be bound already
+    */
+    fn target(&mut self, name: &str, span: Span) -> Place {
+        match self.operand(name, span) {
+            Operand::Slot(slot) => Place::Slot(slot),
+            _ => Place::Name(self.intern(name)),
+        }
+    }
+
+    /*
+        Notes that the binding a name or field path starts from is read, for `finish` to check it is bound somewhere
+    */
+    fn read(&mut self, name: &str, span: Span) {
+        let root = name.split('.').next().unwrap_or(name);
+        if !self.bound.contains(root) {
+            self.reads.push((root.to_owned(), span));
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        match self
+            .chunk
+            .constants
+            .iter()
+            .position(|constant| *constant == value)
+        {
+            Some(index) => index,
+            None => {
+                self.chunk.constants.push(value);
+                self.chunk.constants.len() - 1
+            }
+        }
+    }
+
+    fn intern(&mut self, name: &str) -> usize {
+        match self
+            .chunk
+            .names
+            .iter()
+            .position(|existing| existing == name)
+        {
+            Some(index) => index,
+            None => {
+                self.chunk.names.push(name.to_owned());
+                self.chunk.names.len() - 1
+            }
+        }
+    }
+
+    fn layout(
+        &mut self,
+        type_name: String,
+        prototype: Option<String>,
+        fields: Vec<String>,
+    ) -> usize {
+        self.chunk.layouts.push(Layout {
+            type_name,
+            prototype,
+            fields,
+        });
+        self.chunk.layouts.len() - 1
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    /*
+        Points the jump at `index` to the next instruction to be emitted
+    */
+    fn patch(&mut self, index: usize) {
+        let here = self.chunk.code.len();
+        match &mut self.chunk.code[index] {
+            Op::Jump(target) | Op::JumpUnless { target, .. } | Op::LoopUnless { target, .. } => {
+                *target = here
+            }
+            other => unreachable!("Cannot patch {:?}", other),
+        }
+    }
+
+    fn word<'a>(&self, source: &mut Tokens<'a>, keyword: &str) -> Result<&'a str> {
+        source.next().ok_or_else(|| {
+            NekoError::at(
+                format!("Unexpected end of program after {}", keyword),
+                source.span(),
+            )
+        })
+    }
+
+    fn name<'a>(
+        &self,
+        source: &mut Tokens<'a>,
+        keyword: &str,
+    ) -> Result<(&'a str, Option<&'a str>)> {
+        source.next_name().ok_or_else(|| {
+            NekoError::at(
+                format!("Unexpected end of program after {}", keyword),
+                source.span(),
+            )
+        })
+    }
+}
+
+/*
+    The names declared by `const` anywhere in the code, which are constants wherever they are used
+*/
+fn declared_constants(words: &[Word]) -> Vec<String> {
+    let mut source = Tokens::new(words);
+    let mut constants = vec![];
+    while let Some(word) = source.next() {
+        if word == "const" {
+            if let Some((name, _)) = source.next_name() {
+                constants.push(name.to_owned());
+            }
+        }
+    }
+    constants
+}
@@ -0,0 +1,68 @@
+use crate::lexeme::Span;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, NekoError>;
+
+/*
+    What kind of error stopped a program, so a host can tell a limit it set apart from a bug in the program
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    OutOfFuel,
+    OutOfMemory,
+    StackOverflow,
+    Interrupted,
+}
+
+/*
+    An error raised while running a program, with the position of the word that caused it when known
+*/
+#[derive(Debug)]
+pub struct NekoError {
+    pub message: String,
+    pub span: Option<Span>,
+    pub kind: ErrorKind,
+}
+
+impl NekoError {
+    pub fn new(message: impl Into<String>) -> Self {
+        NekoError {
+            message: message.into(),
+            span: None,
+            kind: ErrorKind::Other,
+        }
+    }
+
+    pub fn at(message: impl Into<String>, span: Span) -> Self {
+        NekoError {
+            message: message.into(),
+            span: Some(span),
+            kind: ErrorKind::Other,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+}
+
+impl fmt::Display for NekoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}", self.message, span),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for NekoError {}
+
+impl From<io::Error> for NekoError {
+    fn from(error: io::Error) -> Self {
+        NekoError::new(error.to_string())
+    }
+}
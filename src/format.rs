@@ -0,0 +1,355 @@
+use crate::error::{NekoError, Result};
+use crate::value::Value;
+
+/*
+    Fills in a template such as `x = {x}, avg = {avg:.2}`. Each `{expression}` or `{expression:spec}`
+    is replaced by the value of the expression, and `{{` and `}}` stand for literal braces.
+    An expression is a sum or product of literals and names, e.g. `{total / count}` or `{p.x * 2}`.
+*/
+pub fn interpolate(template: &str, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<String> {
+    let mut output = String::new();
+    for segment in split_template(template)? {
+        match segment {
+            Segment::Text(text) => output.push_str(&text),
+            Segment::Placeholder(expression, spec) => {
+                let value = evaluate(&expression, lookup)?;
+                output.push_str(&Spec::parse(&spec)?.apply(&value)?);
+            }
+        }
+    }
+    Ok(output)
+}
+
+/*
+    The names the placeholders of a template read, so they can be checked before the template is filled in
+*/
+pub fn names(template: &str) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for segment in split_template(template)? {
+        if let Segment::Placeholder(expression, spec) = segment {
+            Spec::parse(&spec)?;
+            names.extend(split_expression(&expression).into_iter().filter(|token| {
+                !"+-*/()".contains(token.as_str())
+                    && token.parse::<i32>().is_err()
+                    && token.parse::<f32>().is_err()
+            }));
+        }
+    }
+    Ok(names)
+}
+
+enum Segment {
+    Text(String),
+    Placeholder(String, String),
+}
+
+fn split_template(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(NekoError::new("Unclosed { in format string")),
+                    }
+                }
+                let (expression, spec) = match placeholder.split_once(':') {
+                    Some((expression, spec)) => (expression, spec),
+                    None => (placeholder.as_str(), ""),
+                };
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+                segments.push(Segment::Placeholder(expression.to_owned(), spec.to_owned()));
+            }
+            '}' => {
+                return Err(NekoError::new(
+                    "Unmatched } in format string, use }} for a brace",
+                ))
+            }
+            _ => text.push(c),
+        }
+    }
+    segments.push(Segment::Text(text));
+    Ok(segments)
+}
+
+/*
+    How a value is laid out: `[[fill]align][0][width][.precision][type]`, where align is `<`, `>` or `^`
+    and type is `x` or `X` for hexadecimal, `b` for binary or `o` for octal
+*/
+struct Spec {
+    fill: char,
+    align: Option<char>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Result<Spec> {
+        let invalid = || NekoError::new(format!("Invalid format spec: {}", spec));
+        let chars: Vec<char> = spec.chars().collect();
+        let mut parsed = Spec {
+            fill: ' ',
+            align: None,
+            zero: false,
+            width: 0,
+            precision: None,
+            kind: None,
+        };
+        let mut position = 0;
+        let is_align = |c: Option<&char>| matches!(c, Some('<') | Some('>') | Some('^'));
+        if is_align(chars.get(1)) {
+            parsed.fill = chars[0];
+            parsed.align = Some(chars[1]);
+            position = 2;
+        } else if is_align(chars.first()) {
+            parsed.align = Some(chars[0]);
+            position = 1;
+        }
+        if chars.get(position) == Some(&'0') {
+            parsed.zero = true;
+            position += 1;
+        }
+        let digits = |position: &mut usize| {
+            let start = *position;
+            while chars.get(*position).is_some_and(|c| c.is_ascii_digit()) {
+                *position += 1;
+            }
+            chars[start..*position].iter().collect::<String>()
+        };
+        let width = digits(&mut position);
+        if !width.is_empty() {
+            parsed.width = width.parse().map_err(|_| invalid())?;
+        }
+        if chars.get(position) == Some(&'.') {
+            position += 1;
+            let precision = digits(&mut position);
+            parsed.precision = Some(precision.parse().map_err(|_| invalid())?);
+        }
+        match chars.get(position) {
+            Some(&kind) if matches!(kind, 'x' | 'X' | 'b' | 'o') => {
+                parsed.kind = Some(kind);
+                position += 1;
+            }
+            _ => {}
+        }
+        if position != chars.len() {
+            return Err(invalid());
+        }
+        Ok(parsed)
+    }
+
+    fn apply(&self, value: &Value) -> Result<String> {
+        let text = match (self.kind, value) {
+            (Some(kind), Value::Int(number)) => match kind {
+                'x' => format!("{:x}", number),
+                'X' => format!("{:X}", number),
+                'b' => format!("{:b}", number),
+                _ => format!("{:o}", number),
+            },
+            (Some(kind), other) => {
+                return Err(NekoError::new(format!(
+                    "Format type {} needs an integer, found {}",
+                    kind, other
+                )))
+            }
+            (None, Value::Float(number)) => match self.precision {
+                Some(precision) => format!("{:.*}", precision, number),
+                None => number.to_string(),
+            },
+            (None, Value::Int(number)) => match self.precision {
+                Some(precision) => format!("{:.*}", precision, *number as f64),
+                None => number.to_string(),
+            },
+            (None, other) => match self.precision {
+                Some(precision) => other.to_string().chars().take(precision).collect(),
+                None => other.to_string(),
+            },
+        };
+
+        let length = text.chars().count();
+        if length >= self.width {
+            return Ok(text);
+        }
+        let padding = self.width - length;
+        let numeric = matches!(value, Value::Int(_) | Value::Float(_));
+        if self.zero && self.align.is_none() && numeric {
+            let (sign, digits) = match text.strip_prefix('-') {
+                Some(digits) => ("-", digits),
+                None => ("", text.as_str()),
+            };
+            return Ok(format!("{}{}{}", sign, "0".repeat(padding), digits));
+        }
+        let fill = |count: usize| self.fill.to_string().repeat(count);
+        let align = self.align.unwrap_or(if numeric { '>' } else { '<' });
+        let padded = match align {
+            '<' => format!("{}{}", text, fill(padding)),
+            '>' => format!("{}{}", fill(padding), text),
+            _ => format!(
+                "{}{}{}",
+                fill(padding / 2),
+                text,
+                fill(padding - padding / 2)
+            ),
+        };
+        Ok(padded)
+    }
+}
+
+/*
+    Evaluates `+ - * /` over literals and names, with `*` and `/` binding tighter
+    and parentheses for grouping. Integers become floats when mixed with floats.
+*/
+pub fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value> {
+    let tokens = split_expression(expression);
+    let mut position = 0;
+    let value = sum(&tokens, &mut position, lookup)?;
+    if position != tokens.len() {
+        return Err(NekoError::new(format!(
+            "Invalid expression: {}",
+            expression
+        )));
+    }
+    Ok(value)
+}
+
+fn split_expression(expression: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let after_operator = tokens
+            .last()
+            .is_none_or(|last: &String| ["+", "-", "*", "/", "("].contains(&last.as_str()));
+        let negative = c == '-'
+            && current.is_empty()
+            && after_operator
+            && chars.peek().is_some_and(char::is_ascii_digit);
+        if "+-*/()".contains(c) && !negative {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            tokens.push(c.to_string());
+        } else if c.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn sum(
+    tokens: &[String],
+    position: &mut usize,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value> {
+    let mut value = product(tokens, position, lookup)?;
+    while let Some(operator) = tokens
+        .get(*position)
+        .filter(|token| *token == "+" || *token == "-")
+    {
+        *position += 1;
+        let right = product(tokens, position, lookup)?;
+        value = arithmetic(operator, value, right)?;
+    }
+    Ok(value)
+}
+
+fn product(
+    tokens: &[String],
+    position: &mut usize,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value> {
+    let mut value = term(tokens, position, lookup)?;
+    while let Some(operator) = tokens
+        .get(*position)
+        .filter(|token| *token == "*" || *token == "/")
+    {
+        *position += 1;
+        let right = term(tokens, position, lookup)?;
+        value = arithmetic(operator, value, right)?;
+    }
+    Ok(value)
+}
+
+fn term(
+    tokens: &[String],
+    position: &mut usize,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value> {
+    let token = tokens
+        .get(*position)
+        .ok_or_else(|| NekoError::new("Missing value in expression"))?;
+    *position += 1;
+    if token == "(" {
+        let value = sum(tokens, position, lookup)?;
+        if tokens.get(*position).map(String::as_str) != Some(")") {
+            return Err(NekoError::new("Missing ) in expression"));
+        }
+        *position += 1;
+        return Ok(value);
+    }
+    if let Ok(value) = token.parse() {
+        Ok(Value::Int(value))
+    } else if let Ok(value) = token.parse() {
+        Ok(Value::Float(value))
+    } else {
+        lookup(token).ok_or_else(|| NekoError::new(format!("Unknown value: {}", token)))
+    }
+}
+
+fn arithmetic(operator: &str, left: Value, right: Value) -> Result<Value> {
+    match (left, right) {
+        (Value::None, _) | (_, Value::None) => {
+            Err(NekoError::new(format!("Cannot apply {} to none", operator)))
+        }
+        (Value::Int(left), Value::Int(right)) => {
+            let result = match operator {
+                "+" => left.checked_add(right),
+                "-" => left.checked_sub(right),
+                "*" => left.checked_mul(right),
+                _ if right == 0 => return Err(NekoError::new("Division by zero")),
+                _ => left.checked_div(right),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| NekoError::new("Integer overflow"))
+        }
+        (Value::Int(left), Value::Float(right)) => {
+            arithmetic(operator, Value::Float(left as f32), Value::Float(right))
+        }
+        (Value::Float(left), Value::Int(right)) => {
+            arithmetic(operator, Value::Float(left), Value::Float(right as f32))
+        }
+        (Value::Float(left), Value::Float(right)) => Ok(Value::Float(match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            _ => left / right,
+        })),
+        (left, right) => Err(NekoError::new(format!(
+            "Cannot apply {} to {} and {}",
+            operator, left, right
+        ))),
+    }
+}
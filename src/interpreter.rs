@@ -1,534 +1,1960 @@
-use crate::lexeme::{Lexeme, Comparison};
+use crate::builtins::{self, Argument};
+use crate::bytecode::{Arithmetic, Chunk, Slots};
+use crate::checker::Checker;
+use crate::compiler::Compiler;
+use crate::error::{ErrorKind, NekoError, Result};
+use crate::format;
+use crate::lexeme::{self, Comparison, Lexeme, Span, Tokens, TupleElement, Word};
+use crate::native::NativeFunction;
+use crate::optimizer::{self, Optimization};
+use crate::types::{self, Signature, Type};
+use crate::value::{self, EnumValue, Fields, Struct, Value};
 use std::collections::HashMap;
 use std::io;
-use std::io::Result;
 use std::io::Stdout;
 use std::io::Write;
-use std::ops::Add;
-use std::ops::Div;
-use std::ops::Mul;
-use std::ops::Sub;
+use std::io::{BufRead, Read};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+mod vm;
+
+/*
+    A function or method: its words for the tree-walking interpreter and the same body compiled for the stack machine
+*/
+#[derive(Clone, Debug)]
 pub struct Function {
-    parameters: Vec<String>,
-    code: Vec<String>,
+    pub(crate) signature: Signature,
+    pub(crate) code: Vec<Word>,
+    pub(crate) chunk: Rc<Chunk>,
+}
+
+/*
+    A variant of an enum type and the names of its payload fields
+*/
+#[derive(Clone, Debug)]
+pub struct Variant {
+    pub(crate) name: String,
+    pub(crate) fields: Vec<String>,
 }
 
-pub struct Interpreter<T: Write> {
-    variables: HashMap<String, i32>,
-    arrays: HashMap<String, Vec<i32>>,
-    strings: HashMap<String, String>,
-    float: HashMap<String, f32>,
+/*
+    What the interpreter should do after a statement has been executed
+*/
+enum Flow {
+    Next,
+    Return(Option<Value>),
+    Break,
+    Continue,
+}
+
+/*
+    The function a call runs, the name it is defined with, and the struct a method is called on
+*/
+type Callee<'a> = (Function, String, Option<(&'a str, Span)>);
+
+/*
+    What a call in progress took from its caller, given back when it returns
+*/
+struct Frame {
+    caller_scope: Scope,
+    held: usize,
+    instance: Option<Value>,
+}
+
+/*
+    The bindings visible to the code being executed. Function calls swap in a fresh scope
+    holding only their parameters, and restore the caller's scope when they return.
+    `bytes` is the size of every value bound, as the memory limit counts it.
+*/
+#[derive(Default)]
+struct Scope {
+    slots: Rc<Slots>,
+    values: Vec<Option<Value>>,
+    bytes: usize,
+}
+
+impl Scope {
+    fn new(slots: Rc<Slots>) -> Self {
+        Scope {
+            values: vec![None; slots.len()],
+            slots,
+            bytes: 0,
+        }
+    }
+
+    /*
+        Takes the slots compiled code was given, keeping the values of the names it shares with this scope
+    */
+    fn extend(&mut self, slots: Rc<Slots>) {
+        self.values.resize(slots.len(), None);
+        self.slots = slots;
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(self.slots.get(name)?)?.as_ref()
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        let slot = self.slots.get(name)?;
+        self.values.get_mut(slot)?.as_mut()
+    }
+
+    /*
+        Binds a value to a name, giving the name a slot when the code running did not
+    */
+    fn set(&mut self, name: &str, value: Value) {
+        let slot = match self.slots.get(name) {
+            Some(slot) => slot,
+            None => Rc::make_mut(&mut self.slots).resolve(name),
+        };
+        if slot >= self.values.len() {
+            self.values.resize(slot + 1, None);
+        }
+        self.put(slot, value);
+    }
+
+    fn put(&mut self, slot: usize, value: Value) {
+        self.bytes += value.size();
+        if let Some(old) = self.values[slot].replace(value) {
+            self.bytes -= old.size();
+        }
+    }
+
+    /*
+        The size of the value a name is bound to, or nothing when it is unbound
+    */
+    fn size(&self, name: &str) -> usize {
+        self.get(name).map_or(0, Value::size)
+    }
+
+    fn remove(&mut self, name: &str) -> Option<Value> {
+        let slot = self.slots.get(name)?;
+        let value = self.values.get_mut(slot)?.take()?;
+        self.bytes -= value.size();
+        Some(value)
+    }
+
+    /*
+        Every bound name with its value, in the order of their slots
+    */
+    fn bindings(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.slots
+            .names()
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| Some((name, value.as_ref()?)))
+    }
+}
+
+/*
+    A cloneable handle to stop an interpreter from another thread. The run in progress, or the next one
+    when none is, stops with an interrupted error, and later runs go on as usual. An interrupt the run
+    in progress ends before noticing is dropped with it, so it does not stop the next run instead.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    /*
+        Whether an interrupt is waiting, clearing it so it stops only one run
+    */
+    fn take(&self) -> bool {
+        self.interrupted.swap(false, Ordering::Relaxed)
+    }
+}
+
+/*
+    Standard input read a line at a time, taking nothing past the line asked for, so programs
+    can share it with other readers of stdin such as the REPL
+*/
+#[derive(Default)]
+pub struct StdinLines {
+    line: String,
+    consumed: usize,
+}
+
+impl Read for StdinLines {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for StdinLines {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed == self.line.len() {
+            self.line.clear();
+            self.consumed = 0;
+            io::stdin().read_line(&mut self.line)?;
+        }
+        Ok(&self.line.as_bytes()[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed = (self.consumed + amount).min(self.line.len());
+    }
+}
+
+/*
+    How many steps a run takes between checks for an interrupt or a deadline
+*/
+const INTERRUPT_INTERVAL: u64 = 1024;
+
+/*
+    What a call in progress costs besides the values bound in it, counting the stack the interpreter uses to run it
+*/
+const FRAME_BYTES: usize = 8 * 1024;
+
+/*
+    How many calls can be in progress at once unless set otherwise. Every call runs on the stack of the
+    thread running the interpreter, so this keeps runaway recursion from overflowing it. At about 8 KiB
+    a call at most, even in a debug build, it fits in the 2 MiB a thread gets by default.
+*/
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 200;
+
+pub struct Interpreter<T: Write, R: BufRead = StdinLines> {
+    scope: Scope,
     functions: HashMap<String, Function>,
-    structs: HashMap<String, HashMap<String, i32>>,
+    natives: HashMap<String, NativeFunction>,
+    enum_types: HashMap<String, Vec<Variant>>,
+    constants: HashMap<String, Value>,
+    optimization: Optimization,
+    fuel: Option<u64>,
+    fuel_used: u64,
+    memory_limit: Option<usize>,
+    held: usize,
+    max_call_depth: usize,
+    call_depth: usize,
+    interrupt: InterruptHandle,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
     output_stream: T,
+    input_stream: R,
 }
 
 impl Interpreter<Stdout> {
     pub fn new() -> Interpreter<io::Stdout> {
-        Interpreter {
-            variables: HashMap::new(),
-            arrays: HashMap::new(),
-            strings: HashMap::new(),
-            float: HashMap::new(),
-            functions: HashMap::new(),
-            structs: HashMap::new(),
-            output_stream: io::stdout(),
-        }
+        Self::new_with_output_stream(io::stdout())
+    }
+}
+
+impl Default for Interpreter<Stdout> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<T: Write> Interpreter<T> {
     pub fn new_with_output_stream(output_stream: T) -> Self {
+        Self::new_with_streams(output_stream, StdinLines::default())
+    }
+}
+
+impl<T: Write, R: BufRead> Interpreter<T, R> {
+    /*
+        An interpreter that writes to `output_stream` and reads what `input` statements ask for from `input_stream`
+    */
+    pub fn new_with_streams(output_stream: T, input_stream: R) -> Self {
         Interpreter {
-            variables: HashMap::new(),
-            arrays: HashMap::new(),
-            strings: HashMap::new(),
-            float: HashMap::new(),
+            scope: Scope::default(),
             functions: HashMap::new(),
-            structs: HashMap::new(),
-            output_stream: output_stream,
+            natives: HashMap::new(),
+            enum_types: HashMap::new(),
+            constants: HashMap::new(),
+            optimization: Optimization::Off,
+            fuel: None,
+            fuel_used: 0,
+            memory_limit: None,
+            held: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            call_depth: 0,
+            interrupt: InterruptHandle::default(),
+            timeout: None,
+            deadline: None,
+            output_stream,
+            input_stream,
+        }
+    }
+
+    /*
+        How much programs are optimised before they run, which is not at all unless set
+    */
+    pub fn set_optimization(&mut self, optimization: Optimization) {
+        self.optimization = optimization;
+    }
+
+    /*
+        Limits how many steps each run may take before it stops with an out of fuel error.
+        `None`, the default, lets programs run for as long as they need.
+    */
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /*
+        How many steps the last run took: one per instruction on the stack machine,
+        and one per statement or pass of a loop when walking the program
+    */
+    pub fn fuel_used(&self) -> u64 {
+        self.fuel_used
+    }
+
+    /*
+        Limits how many bytes the variables of every call in progress may hold together, counting
+        what each call costs besides its variables. `None`, the default, sets no limit.
+    */
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /*
+        How many bytes the variables and calls in progress hold, as counted against the memory limit
+    */
+    pub fn memory_used(&self) -> usize {
+        self.held + self.scope.bytes
+    }
+
+    /*
+        Limits how many calls can be in progress at once before a call fails with a stack overflow error,
+        which is `DEFAULT_MAX_CALL_DEPTH` unless set. Every call takes some of the stack of the thread
+        running the interpreter, so a thread with a small stack may need a lower limit.
+    */
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /*
+        Makes a Rust function callable from programs, as in `call name arguments... into result`.
+        It takes precedence over functions, methods and enum variants of the same name,
+        and is kept when the interpreter is reset. The helpers in `native` convert its arguments.
+    */
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Value]) -> Result<Value> + 'static,
+    ) {
+        self.natives.insert(name.to_owned(), Rc::new(function));
+    }
+
+    /*
+        A handle other threads can use to interrupt whatever this interpreter is running
+    */
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /*
+        Limits how long each run may take before it is interrupted. `None`, the default, sets no limit.
+    */
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /*
+        Starts counting the fuel and time of a new run
+    */
+    fn start(&mut self) {
+        self.fuel_used = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /*
+        Runs `body` as one run, then drops any interrupt it ended without noticing, whether it finished or failed
+    */
+    fn finishing<V>(&mut self, body: impl FnOnce(&mut Self) -> Result<V>) -> Result<V> {
+        let result = body(self);
+        self.interrupt.take();
+        result
+    }
+
+    /*
+        Takes one step from the fuel of the current run, failing at `span` once there is none left.
+        Every so many steps, also fails when the run was interrupted or is past its deadline.
+    */
+    fn step(&mut self, span: Span) -> Result<()> {
+        if let Some(fuel) = self.fuel {
+            if self.fuel_used >= fuel {
+                return Err(
+                    NekoError::at(format!("Out of fuel after {} steps", fuel), span)
+                        .with_kind(ErrorKind::OutOfFuel),
+                );
+            }
         }
+        if self.fuel_used.is_multiple_of(INTERRUPT_INTERVAL) {
+            if self.interrupt.take() {
+                return Err(NekoError::at("Interrupted", span).with_kind(ErrorKind::Interrupted));
+            }
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                let message = "Interrupted after running out of time";
+                return Err(NekoError::at(message, span).with_kind(ErrorKind::Interrupted));
+            }
+        }
+        self.fuel_used += 1;
+        Ok(())
     }
 
+    /*
+        Checks a program and compiles it against everything defined so far, optimised as set
+    */
+    pub fn compile(&self, source_code: &str) -> Result<Chunk> {
+        let words = lexeme::tokenize(source_code);
+        self.checker().check(&words)?;
+        let constants = self.constants.keys().cloned().collect();
+        let chunk = Compiler::compile(&words, &self.scope.slots, &constants)?;
+        Ok(optimizer::optimize(chunk, self.optimization))
+    }
+
+    /*
+        Checks a program, compiles it and runs it on the stack machine
+    */
     pub fn run(&mut self, source_code: &str) -> Result<&T> {
-        let mut source = source_code.split_whitespace();
+        self.finishing(|this| {
+            let chunk = this.compile(source_code)?;
+            this.scope.extend(Rc::clone(&chunk.slots));
+            this.start();
+            this.execute_chunk(&chunk)
+        })?;
+
+        Ok(self.output_stream.by_ref())
+    }
+
+    /*
+        Runs a program by walking its words, as the interpreter did before programs were compiled.
+        The output is the same as `run`, only slower, which makes it a reference for the stack machine.
+    */
+    pub fn run_tree_walking(&mut self, source_code: &str) -> Result<&T> {
+        self.finishing(|this| {
+            let words = lexeme::tokenize(source_code);
+            this.checker().check(&words)?;
+            this.start();
+            let mut source = Tokens::new(&words);
+            match this.execute_block(&mut source, &[])? {
+                Flow::Break | Flow::Continue => Err(NekoError::at(
+                    "break or continue outside of a loop",
+                    source.span(),
+                )),
+                _ => Ok(()),
+            }
+        })?;
+
+        Ok(self.output_stream.by_ref())
+    }
+
+    /*
+        A checker that knows everything defined by earlier runs, so a program can build on them
+    */
+    fn checker(&self) -> Checker {
+        let mut checker = Checker::new();
+        for (name, value) in self.scope.bindings() {
+            checker.define(name, Type::of(value));
+        }
+        for (name, value) in &self.constants {
+            checker.define_constant(name, Type::of(value));
+        }
+        for (name, function) in &self.functions {
+            checker.define_function(name, function.signature.clone());
+        }
+        for name in self.natives.keys() {
+            checker.define_native(name);
+        }
+        for (name, variants) in &self.enum_types {
+            let variants = variants
+                .iter()
+                .map(|variant| (variant.name.clone(), variant.fields.len()))
+                .collect();
+            checker.define_enum(name, variants);
+        }
+        checker
+    }
+
+    /*
+        Executes statements until one of `terminators` is reached, leaving it unconsumed
+    */
+    fn execute_block(&mut self, source: &mut Tokens, terminators: &[&str]) -> Result<Flow> {
+        while let Some(word) = source.peek() {
+            if terminators.contains(&word) {
+                break;
+            }
+            source.next();
+            self.step(source.span())?;
+            let flow = self.execute_statement(word, source)?;
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /*
+        Runs one statement. Those that run blocks of their own have a function each, and the rest are run
+        by `execute_simple`, keeping this frame small since every call a program makes adds one to the native stack.
+    */
+    fn execute_statement(&mut self, word: &str, source: &mut Tokens) -> Result<Flow> {
+        match Lexeme::from_str(word) {
+            Lexeme::Match => self.execute_match(source),
+            Lexeme::Switch => self.execute_switch(source),
+            Lexeme::Call => self.execute_call(source),
+            Lexeme::If => self.execute_if(source),
+            Lexeme::Loop => self.execute_loop(source),
+            Lexeme::Foreach => self.execute_foreach(source),
+            keyword => self.execute_simple(keyword, word, source),
+        }
+    }
+
+    /*
+        Runs the case of a `switch` matching its value, if any
+    */
+    fn execute_switch(&mut self, source: &mut Tokens) -> Result<Flow> {
+        let name = source.next().unwrap();
+        let value = self.integer(name, source.span())?;
         while let Some(word) = source.next() {
-            let keyword = Lexeme::from_str(word);
-            match keyword {
-                Lexeme::Var => {
-                    let name = source.next().unwrap();
-                    let value = source.next().unwrap().parse().unwrap();
-                    self.variables.insert(name.to_owned(), value);
-                }
-                Lexeme::Array => {
-                    let name = source.next().unwrap();
-                    let size = source.next().unwrap().parse().unwrap();
-                    let mut array = Vec::with_capacity(size);
-                    for _ in 0..size {
-                        let value = source.next().unwrap().parse().unwrap();
-                        array.push(value);
+            match word {
+                "endswitch" => break,
+                "break" => continue,
+                "case" => {
+                    let case_value: i32 = source.next().unwrap().parse().unwrap();
+                    let terminators = ["break", "case", "endswitch"];
+                    if value != case_value {
+                        source.skip_block(&terminators);
+                        continue;
                     }
-                    self.arrays.insert(name.to_owned(), array);
-                }
-                Lexeme::String => {
-                    let name = source.next().unwrap();
-                    let mut value = String::new();
-                    while let Some(word) = source.next() {
-                        if word == "endstring" {
-                            break;
-                        }
-                        value.push_str(word);
-                        value.push(' ');
+                    let flow = self.execute_block(source, &terminators)?;
+                    if matches!(flow, Flow::Return(_) | Flow::Continue) {
+                        return Ok(flow);
                     }
-                    value.pop();
-                    self.strings.insert(name.to_owned(), value);
+                    source.skip_block(&["endswitch"]);
                 }
-                Lexeme::Float => {
-                    let name = source.next().unwrap();
-                    let value = source.next().unwrap().parse().unwrap();
-                    self.float.insert(name.to_owned(), value);
+                _ => {
+                    return Err(NekoError::at(
+                        format!("Expected case in switch, found {}", word),
+                        source.span(),
+                    ))
                 }
-                Lexeme::Function => {
-                    let name = source.next().unwrap();
-                    let mut function = vec![];
-                    let mut parameters = vec![];
-                    while let Some(word) = source.next() {
-                        if word == "end" {
-                            break;
-                        }
-                        if word == "with" {
-                            while let Some(word) = source.next() {
-                                if word == "end" {
-                                    break;
-                                }
-                                match word {
-                                    "print" => {
-                                        let name = source.next().unwrap();
-                                        writeln!(self.output_stream, "{}", self.variables[name])?;
-                                    }
-                                    "var" => {
-                                        let name = source.next().unwrap();
-                                        let value = source.next().unwrap().parse().unwrap();
-                                        self.variables.insert(name.to_owned(), value);
-                                    }
-                                    "add" => {
-                                        let name1 = source.next().unwrap();
-                                        let name2 = source.next().unwrap();
-                                        *self.variables.get_mut(name1).unwrap() +=
-                                            self.variables[name2];
-                                    }
-                                    _ => {}
-                                }
-                                parameters.push(word.to_owned());
-                            }
-                            break;
-                        }
-                        function.push(word.to_owned());
-                    }
-                    self.functions.insert(
-                        name.to_owned(),
-                        Function {
-                            parameters,
-                            code: function,
-                        },
-                    );
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /*
+        Calls a native or program function, or builds an enum value, binding the result after `into`
+    */
+    fn execute_call(&mut self, source: &mut Tokens) -> Result<Flow> {
+        let target = source.next().unwrap();
+        if self.execute_native_or_variant(target, source)? {
+            return Ok(Flow::Next);
+        }
+        let span = source.span();
+        let (function, function_name, receiver) = self.function_for(target, span)?;
+        let arguments = self.read_arguments(&function, source)?;
+        let result = self.invoke(&function, receiver, arguments, Some(span), |this| {
+            this.execute_block(&mut Tokens::new(&function.code), &[])
+        })?;
+        let result = self.returned(&function, &function_name, result, span)?;
+        self.bind_result(&function_name, result, source, span)?;
+        Ok(Flow::Next)
+    }
+
+    /*
+        Calls a native function or builds an enum value, neither of which runs any code of the program,
+        telling whether `target` was one of them
+    */
+    fn execute_native_or_variant(&mut self, target: &str, source: &mut Tokens) -> Result<bool> {
+        if let Some(native) = self.natives.get(target).cloned() {
+            let span = source.span();
+            let mut arguments = vec![];
+            while let Some(word) = source.peek() {
+                if word == "into" || lexeme::ends_list(word) {
+                    break;
                 }
-                Lexeme::Struct => {
-                    let name = source.next().unwrap();
-                    let mut struct_fields = HashMap::new();
-                    while let Some(field) = source.next() {
-                        if field == "endstruct" {
-                            break;
-                        }
-                        let value = source.next().unwrap().parse().unwrap();
-                        struct_fields.insert(field.to_owned(), value);
+                source.next();
+                arguments.push(self.read_operand(word, source.span())?);
+            }
+            let value = call_native(target, &native, &arguments, span)?;
+            if source.peek() == Some("into") {
+                source.next();
+                let names = source.next_list();
+                self.bind_all(&names, value, source.span())?;
+            }
+            return Ok(true);
+        }
+        if let Some(value) = self.construct_variant(target, source)? {
+            source.next();
+            let name = source.next().unwrap();
+            self.bind(name, value, source.span())?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /*
+        Reads an argument for every parameter of a function, checking those whose type is declared
+    */
+    fn read_arguments(&self, function: &Function, source: &mut Tokens) -> Result<Vec<Value>> {
+        let mut arguments = vec![];
+        for parameter in &function.signature.parameters {
+            let argument = self.read_operand(source.next().unwrap(), source.span())?;
+            if let Some(annotation) = &parameter.annotation {
+                self.check_annotation(&parameter.name, Some(annotation), &argument, source.span())?;
+            }
+            arguments.push(argument);
+        }
+        Ok(arguments)
+    }
+
+    /*
+        Binds what a function returned to the names after `into`, when the call has any
+    */
+    fn bind_result(
+        &mut self,
+        function_name: &str,
+        result: Option<Value>,
+        source: &mut Tokens,
+        span: Span,
+    ) -> Result<()> {
+        if source.peek() != Some("into") {
+            return Ok(());
+        }
+        source.next();
+        let names = source.next_list();
+        let value = result.ok_or_else(|| {
+            NekoError::at(format!("{} does not return a value", function_name), span)
+        })?;
+        self.bind_all(&names, value, source.span())
+    }
+
+    /*
+        Runs the branch of an `if` its condition picks
+    */
+    fn execute_if(&mut self, source: &mut Tokens) -> Result<Flow> {
+        if self.read_condition(source)? {
+            let flow = self.execute_block(source, &["else", "end"])?;
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow);
+            }
+            if source.peek() == Some("else") {
+                source.next();
+                source.skip_block(&["end"]);
+            }
+        } else {
+            source.skip_block(&["else", "end"]);
+            if source.peek() == Some("else") {
+                source.next();
+                let flow = self.execute_block(source, &["end"])?;
+                if !matches!(flow, Flow::Next) {
+                    return Ok(flow);
+                }
+            }
+        }
+        source.next();
+        Ok(Flow::Next)
+    }
+
+    /*
+        Reads the `name comparison value` of an `if` and tells whether it holds
+    */
+    fn read_condition(&self, source: &mut Tokens) -> Result<bool> {
+        let name = source.next().unwrap();
+        let span = source.span();
+        let comp = Comparison::from_str(source.next().unwrap());
+        let value = self.number(source.next().unwrap(), source.span())?;
+        Ok(compare(&comp, &self.number(name, span)?, &value))
+    }
+
+    /*
+        Runs the body of a `loop` while its condition holds
+    */
+    fn execute_loop(&mut self, source: &mut Tokens) -> Result<Flow> {
+        let name = source.next().unwrap();
+        let span = source.span();
+        let comp = Comparison::from_str(source.next().unwrap());
+        let value = self.number(source.next().unwrap(), source.span())?;
+        while compare(&comp, &self.number(name, span)?, &value) {
+            self.step(span)?;
+            let mut inner_source = source.clone();
+            match self.execute_block(&mut inner_source, &["end"])? {
+                Flow::Next | Flow::Continue => {}
+                Flow::Break => {
+                    source.skip_block(&["end"]);
+                    source.next();
+                    return Ok(Flow::Next);
+                }
+                flow => return Ok(flow),
+            }
+        }
+        // Once the condition fails the body is read one last time, as loops always have
+        let flow = self.execute_block(source, &["end"])?;
+        if !matches!(flow, Flow::Next) {
+            return Ok(flow);
+        }
+        source.next();
+        Ok(Flow::Next)
+    }
+
+    /*
+        Runs the body of a `foreach` once for every entry of a collection
+    */
+    fn execute_foreach(&mut self, source: &mut Tokens) -> Result<Flow> {
+        let names: Vec<&str> = source.by_ref().take_while(|&word| word != "in").collect();
+        let name = source.next().unwrap();
+        let collection = match lexeme::unquote(name) {
+            Some(text) => Value::Str(text),
+            None => self.lookup(name).ok_or_else(|| {
+                NekoError::at(format!("Unknown variable: {}", name), source.span())
+            })?,
+        };
+        let entries = entries(collection, source.span())?;
+        if names.is_empty() || names.len() > 2 {
+            return Err(NekoError::at(
+                "foreach expects one or two names before in",
+                source.span(),
+            ));
+        }
+        for (key, item) in entries {
+            if let [key_name, _] = names[..] {
+                self.bind(key_name, key, source.span())?;
+            }
+            self.bind(names[names.len() - 1], item, source.span())?;
+            let mut body = source.clone();
+            match self.execute_block(&mut body, &["end"])? {
+                Flow::Next | Flow::Continue => {}
+                Flow::Break => break,
+                flow => return Ok(flow),
+            }
+        }
+        source.skip_block(&["end"]);
+        source.next();
+        Ok(Flow::Next)
+    }
+
+    /*
+        Runs a statement that does not run a block of its own
+    */
+    fn execute_simple(&mut self, keyword: Lexeme, word: &str, source: &mut Tokens) -> Result<Flow> {
+        if keyword.assigns() {
+            if let Some(name) = source.peek() {
+                self.check_mutable(name, source.span())?;
+            }
+        }
+        match keyword {
+            Lexeme::Var => {
+                let (name, annotation) = source.next_name().unwrap();
+                let value = match source.next().unwrap() {
+                    "none" => Value::None,
+                    word => {
+                        Value::Int(lexeme::boolean(word).unwrap_or_else(|| word.parse().unwrap()))
+                    }
+                };
+                self.check_annotation(name, annotation, &value, source.span())?;
+                self.bind(name, value, source.span())?;
+            }
+            Lexeme::Set => {
+                let name = source.next().unwrap();
+                let span = source.span();
+                let value = self.read_value(name, source)?;
+                self.bind(name, value, span)?;
+            }
+            Lexeme::Const => {
+                let (name, annotation) = source.next_name().unwrap();
+                let span = source.span();
+                let value = self.read_value(name, source)?;
+                self.check_annotation(name, annotation, &value, span)?;
+                self.define_constant(name, value, span)?;
+            }
+            Lexeme::Array => {
+                let (name, annotation) = source.next_name().unwrap();
+                let span = source.span();
+                let array = Value::Array(self.read_array(name, word, source)?);
+                self.check_annotation(name, annotation, &array, span)?;
+                self.bind_local(name, array, span)?;
+            }
+            Lexeme::String => {
+                let (name, annotation) = source.next_name().unwrap();
+                let span = source.span();
+                self.check_annotation(name, annotation, &Value::Str(String::new()), span)?;
+                let mut value = String::new();
+                for word in source.by_ref() {
+                    if word == "endstring" {
+                        break;
                     }
-                    self.structs.insert(name.to_owned(), struct_fields);
+                    value.push_str(word);
+                    value.push(' ');
                 }
-                Lexeme::Switch => {
-                    let name = source.next().unwrap();
-                    let value = self.variables[name];
-                    let mut found = false;
-                    while let Some(word) = source.next() {
-                        if word == "endswitch" {
+                value.pop();
+                self.bind_local(name, Value::Str(value), span)?;
+            }
+            Lexeme::Float => {
+                let (name, annotation) = source.next_name().unwrap();
+                let value = source.next().unwrap().parse().unwrap();
+                self.check_annotation(name, annotation, &Value::Float(value), source.span())?;
+                self.bind_local(name, Value::Float(value), source.span())?;
+            }
+            Lexeme::Tuple => {
+                let (name, annotation) = source.next_name().unwrap();
+                let elements = source.next_tuple().ok_or_else(|| {
+                    NekoError::at(
+                        format!("Expected (a, b, ...) after tuple {}", name),
+                        source.span(),
+                    )
+                })?;
+                let values = self.read_tuple(&elements, source.span())?;
+                self.check_annotation(name, annotation, &values, source.span())?;
+                self.bind_local(name, values, source.span())?;
+            }
+            Lexeme::Function => {
+                let name = source.next().unwrap();
+                let function = self.read_function(source, false)?;
+                self.functions.insert(name.to_owned(), function);
+            }
+            Lexeme::Method => {
+                let type_name = source.next().unwrap();
+                let name = source.next().unwrap();
+                let function = self.read_function(source, true)?;
+                self.functions
+                    .insert(format!("{}.{}", type_name, name), function);
+            }
+            Lexeme::Struct => {
+                let name = source.next().unwrap();
+                let span = source.span();
+                let instance = self.read_struct(name, source)?;
+                self.bind_local(name, Value::Struct(instance), span)?;
+            }
+            Lexeme::Enum => {
+                let name = source.next().unwrap();
+                let span = source.span();
+                let mut variants = vec![];
+                let mut words = vec![];
+                for word in source.by_ref() {
+                    if word == "|" || word == "endenum" {
+                        let mut words = std::mem::take(&mut words).into_iter();
+                        let variant = words.next().ok_or_else(|| {
+                            NekoError::at(format!("Empty variant in enum {}", name), span)
+                        })?;
+                        variants.push(Variant {
+                            name: variant,
+                            fields: words.collect(),
+                        });
+                        if word == "endenum" {
                             break;
                         }
-                        if found {
-                            continue;
-                        }
-                        if word == "case" {
-                            let case_value = source.next().unwrap().parse().unwrap();
-                            if value == case_value {
-                                found = true;
-                                while let Some(word) = source.next() {
-                                    if word == "break" {
-                                        break;
-                                    }
-                                    match word {
-                                        "print" => {
-                                            let name = source.next().unwrap();
-                                            match self.variables.get(name) {
-                                                Some(value) => println!("{}", value),
-                                                None => match self.arrays.get(name) {
-                                                    Some(array) => {
-                                                        for (index, &value) in
-                                                            array.iter().enumerate()
-                                                        {
-                                                            println!(
-                                                                "{}[{}] = {}",
-                                                                name, index, value
-                                                            );
-                                                        }
-                                                    }
-                                                    None => match self.float.get(name) {
-                                                        Some(value) => println!("{}", value),
-                                                        None => println!("{}", self.strings[name]),
-                                                    },
-                                                },
-                                            }
-                                        }
-                                        _ => continue,
-                                    }
-                                }
-                            }
-                        }
+                    } else {
+                        words.push(word.to_owned());
                     }
                 }
-                Lexeme::Print => {
-                    let name = source.next().unwrap();
-                    match self.variables.get(name) {
-                        Some(value) => writeln!(self.output_stream, "{}", value)?,
-                        None => match self.arrays.get(name) {
-                            Some(array) => {
-                                for (index, &value) in array.iter().enumerate() {
-                                    writeln!(
-                                        self.output_stream,
-                                        "{}[{}] = {}",
-                                        name, index, value
-                                    )?;
-                                }
-                            }
-                            None => match self.float.get(name) {
-                                Some(value) => writeln!(self.output_stream, "{}", value)?,
-                                None => match self.structs.get(name) {
-                                    Some(_struct) => {
-                                        for (key, value) in _struct.iter() {
-                                            writeln!(
-                                                self.output_stream,
-                                                "{}.{} = {}",
-                                                name, key, value
-                                            )?;
-                                        }
-                                    }
-                                    None => writeln!(self.output_stream, "{}", self.strings[name])?,
-                                },
-                            },
-                        },
+                self.enum_types.insert(name.to_owned(), variants);
+            }
+            Lexeme::Print => {
+                let name = source.next().unwrap();
+                match lexeme::unquote(name) {
+                    Some(template) => {
+                        let line = format::interpolate(&template, &|name| self.lookup(name))
+                            .map_err(|error| NekoError::at(error.message, source.span()))?;
+                        writeln!(self.output_stream, "{}", line)?;
                     }
+                    None => self.print(name, source.span())?,
                 }
-                Lexeme::Call => {
-                    // let name = source.next().unwrap();
-                    // let function = &self.functions[name];
-                    // let parameters = &function.parameters;
-                    // let function = self.functions.get(name).unwrap();
-                    // let mut interpreter = Interpreter::new();
-                    // for (param_name, param_value) in function.parameters.iter().zip(parameters) {
-                    //     interpreter
-                    //         .variables
-                    //         .insert(param_name.to_owned(), param_value.parse::<i32>().unwrap());
-                    // }
-                    // interpreter.run(&function.code.join(" "));
-
-                    let name = source.next().unwrap();
-                    let function = &self.functions[name];
-                    let parameters = &function.parameters;
-                    let function_statements = &function.code;
-                    let mut local_variables: HashMap<String, i32> = HashMap::new();
-                    for (name, parameter) in parameters
-                        .iter()
-                        .zip(source.by_ref().take(parameters.len()))
-                    {
-                        local_variables.insert(name.to_owned(), parameter.parse().unwrap());
-                    }
-                    let mut source = function_statements.iter().cloned().peekable();
-                    while let Some(word) = source.next() {
-                        match word.as_str() {
-                            "var" => {
-                                let name = source.next().unwrap();
-                                let value = source.next().unwrap().parse().unwrap();
-                                local_variables.insert(name, value);
-                            }
-                            "print" => {
-                                let name = source.next().unwrap();
-                                writeln!(self.output_stream, "{}", self.variables[&name])?;
-                            }
-                            "add" => {
-                                let name1 = source.next().unwrap();
-                                let name2 = source.next().unwrap();
-                                *self.variables.get_mut(&name1).unwrap() += self.variables[&name2];
-                            }
-                            _ => {}
-                        }
+            }
+            Lexeme::Return => {
+                let span = source.span();
+                let mut values: Vec<Value> = source
+                    .next_list()
+                    .into_iter()
+                    .map(|word| self.read_operand(word, span))
+                    .collect::<Result<_>>()?;
+                let value = match values.len() {
+                    0 => None,
+                    1 => values.pop(),
+                    _ => Some(Value::Tuple(values)),
+                };
+                return Ok(Flow::Return(value));
+            }
+            Lexeme::Break => return Ok(Flow::Break),
+            Lexeme::Continue => return Ok(Flow::Continue),
+            Lexeme::Map => {
+                let (name, annotation) = source.next_name().unwrap();
+                let mut entries = Fields::new();
+                while let Some(key) = source.next() {
+                    if key == "endmap" {
+                        break;
                     }
+                    let value = self.read_value(key, source)?;
+                    let key = lexeme::unquote(key).unwrap_or_else(|| key.to_owned());
+                    entries.insert(key, value);
                 }
-                Lexeme::If => {
-                    let name = source.next().unwrap();
-                    let comp = Comparison::from_str(source.next().unwrap());
-                    let value = source.next().unwrap().parse().unwrap();
-                    let mut executed = false;
-                    let condition = match comp {
-                        Comparison::Equal => self.variables[name] == value,
-                        Comparison::NotEqual => self.variables[name] != value,
-                        Comparison::LessThan => self.variables[name] < value,
-                        Comparison::LessThanOrEqual => self.variables[name] <= value,
-                        Comparison::GreaterThan => self.variables[name] > value,
-                        Comparison::GreaterThanOrEqual => self.variables[name] >= value,
-                    };
-                    if condition {
-                        while let Some(word) = source.next() {
-                            if word == "else" || word == "end" {
-                                break;
-                            }
-                            match word {
-                                "var" => {
-                                    let name = source.next().unwrap();
-                                    let value = source.next().unwrap().parse().unwrap();
-                                    self.variables.insert(name.to_owned(), value);
-                                }
-                                "end" => {
-                                    break;
-                                }
-                                "print" => {
-                                    let name = source.next().unwrap();
-                                    writeln!(self.output_stream, "{}", self.variables[name])?;
-                                }
-                                "add" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() +=
-                                        self.variables[name2];
-                                }
-                                "sub" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() -=
-                                        self.variables[name2];
-                                }
-                                "mul" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() *=
-                                        self.variables[name2];
-                                }
-                                "div" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() /=
-                                        self.variables[name2];
-                                }
-                                _ => {}
-                            }
-                        }
-                    } else {
-                        while let Some(word) = source.next() {
-                            if word == "end" {
-                                break;
-                            }
-                            if word == "else" {
-                                executed = true;
-                                while let Some(word) = source.next() {
-                                    if word == "end" {
-                                        break;
-                                    }
-                                    match word {
-                                        "var" => {
-                                            let name = source.next().unwrap();
-                                            let value = source.next().unwrap().parse().unwrap();
-                                            self.variables.insert(name.to_owned(), value);
-                                        }
-                                        "print" => {
-                                            let name = source.next().unwrap();
-                                            writeln!(
-                                                self.output_stream,
-                                                "{}",
-                                                self.variables[name]
-                                            )?;
-                                        }
-                                        "add" => {
-                                            let name1 = source.next().unwrap();
-                                            let name2 = source.next().unwrap();
-                                            *self.variables.get_mut(name1).unwrap() +=
-                                                self.variables[name2];
-                                        }
-                                        "sub" => {
-                                            let name1 = source.next().unwrap();
-                                            let name2 = source.next().unwrap();
-                                            *self.variables.get_mut(name1).unwrap() -=
-                                                self.variables[name2];
-                                        }
-                                        "mul" => {
-                                            let name1 = source.next().unwrap();
-                                            let name2 = source.next().unwrap();
-                                            *self.variables.get_mut(name1).unwrap() *=
-                                                self.variables[name2];
-                                        }
-                                        "div" => {
-                                            let name1 = source.next().unwrap();
-                                            let name2 = source.next().unwrap();
-                                            *self.variables.get_mut(name1).unwrap() /=
-                                                self.variables[name2];
-                                        }
-                                        "end" => {
-                                            break;
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                            }
-                            if executed {
-                                break;
-                            }
-                        }
+                let entries = Value::Map(entries);
+                self.check_annotation(name, annotation, &entries, source.span())?;
+                self.bind_local(name, entries, source.span())?;
+            }
+            Lexeme::Add => self.integer_arithmetic(Arithmetic::Add, source)?,
+            Lexeme::Sub => self.integer_arithmetic(Arithmetic::Sub, source)?,
+            Lexeme::Mul => self.integer_arithmetic(Arithmetic::Mul, source)?,
+            Lexeme::Div => self.integer_arithmetic(Arithmetic::Div, source)?,
+            Lexeme::POW => self.integer_arithmetic(Arithmetic::Pow, source)?,
+            Lexeme::AddF => self.float_arithmetic(Arithmetic::AddF, source)?,
+            Lexeme::SubF => self.float_arithmetic(Arithmetic::SubF, source)?,
+            Lexeme::MulF => self.float_arithmetic(Arithmetic::MulF, source)?,
+            Lexeme::DivF => self.float_arithmetic(Arithmetic::DivF, source)?,
+            Lexeme::Sqrt => {
+                let name = source.next().unwrap();
+                let value = self.integer(name, source.span())?;
+                let result = (value as f32).sqrt() as i32;
+                self.bind(name, Value::Int(result), source.span())?;
+            }
+            Lexeme::ABS => {
+                let name = source.next().unwrap();
+                let value = self.integer(name, source.span())?.checked_abs();
+                let value =
+                    value.ok_or_else(|| NekoError::at("Integer overflow in abs", source.span()))?;
+                self.bind(name, Value::Int(value), source.span())?;
+            }
+            Lexeme::Concat
+            | Lexeme::Len
+            | Lexeme::Substr
+            | Lexeme::IndexOf
+            | Lexeme::Split
+            | Lexeme::Join
+            | Lexeme::Replace
+            | Lexeme::Trim
+            | Lexeme::Upper
+            | Lexeme::Lower
+            | Lexeme::StartsWith
+            | Lexeme::EndsWith
+            | Lexeme::Repeat
+            | Lexeme::Sort
+            | Lexeme::SortDesc
+            | Lexeme::Reverse
+            | Lexeme::Contains
+            | Lexeme::BinarySearch
+            | Lexeme::Sum
+            | Lexeme::Min
+            | Lexeme::Max
+            | Lexeme::Avg
+            | Lexeme::Slice
+            | Lexeme::Fill
+            | Lexeme::IsNone
+            | Lexeme::IsSome
+            | Lexeme::UnwrapOr
+            | Lexeme::Get => {
+                let span = source.span();
+                let mut arguments = vec![];
+                for _ in 0..builtins::arity(keyword) {
+                    let word = source.next().unwrap();
+                    arguments.push(Argument {
+                        value: self.read_operand(word, source.span())?,
+                        span: source.span(),
+                    });
+                }
+                self.reserve(builtins::allocation(keyword, &arguments), 0, span)?;
+                let value = builtins::call(keyword, &arguments)?;
+                if source.next() != Some("into") {
+                    return Err(NekoError::at(
+                        format!("Expected into after {}", word),
+                        source.span(),
+                    ));
+                }
+                let name = source.next().unwrap();
+                self.bind(name, value, source.span())?;
+            }
+            Lexeme::Input | Lexeme::InputInt | Lexeme::InputFloat => {
+                let name = source.next().unwrap();
+                let value = self.read_input(keyword, source.span())?;
+                self.bind(name, value, source.span())?;
+            }
+            Lexeme::Pop => {
+                let name = source.next().unwrap();
+                let span = source.span();
+                let value = match self.scope.get_mut(name) {
+                    Some(Value::Array(values)) => values.pop(),
+                    _ => {
+                        return Err(NekoError::at(
+                            format!("pop expects an array variable: {}", name),
+                            span,
+                        ))
                     }
+                };
+                self.scope.bytes -= value.as_ref().map_or(0, Value::size);
+                let value = value.unwrap_or(Value::None);
+                if source.next() != Some("into") {
+                    return Err(NekoError::at("Expected into after pop", source.span()));
                 }
-                Lexeme::Loop => {
-                    let name = source.next().unwrap();
-                    let comp = Comparison::from_str(source.next().unwrap());
-                    let value = source.next().unwrap().parse().unwrap();
-                    while match comp {
-                        Comparison::Equal => self.variables[name] == value,
-                        Comparison::NotEqual => self.variables[name] != value,
-                        Comparison::LessThan => self.variables[name] < value,
-                        Comparison::LessThanOrEqual => self.variables[name] <= value,
-                        Comparison::GreaterThan => self.variables[name] > value,
-                        Comparison::GreaterThanOrEqual => self.variables[name] >= value,
-                    } {
-                        let mut inner_source = source.clone();
-                        while let Some(word) = inner_source.next() {
-                            if word == "end" {
-                                break;
+                let target = source.next().unwrap();
+                self.bind(target, value, source.span())?;
+            }
+            Lexeme::End => {}
+            _ => {
+                return Err(NekoError::at(
+                    format!("Unknown command: {}", word),
+                    source.span(),
+                ))
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /*
+        Runs `add`, `sub`, `mul`, `div` or `pow`, storing the result in the variable it starts from
+    */
+    fn integer_arithmetic(&mut self, operation: Arithmetic, source: &mut Tokens) -> Result<()> {
+        let name = source.next().unwrap();
+        let span = source.span();
+        let left = self.integer(name, span)?;
+        let right = self.integer(source.next().unwrap(), source.span())?;
+        if operation == Arithmetic::Div && right == 0 {
+            return Err(NekoError::at("Division by zero", source.span()));
+        }
+        let result = operation
+            .integers(left, right)
+            .map_err(|error| NekoError::at(error.message, span))?;
+        self.bind(name, Value::Int(result), span)
+    }
+
+    /*
+        Runs `add_f`, `sub_f`, `mul_f` or `div_f`, storing the result in the variable it starts from
+    */
+    fn float_arithmetic(&mut self, operation: Arithmetic, source: &mut Tokens) -> Result<()> {
+        let name = source.next().unwrap();
+        let span = source.span();
+        let left = self.real(name, span)?;
+        let right = self.real(source.next().unwrap(), source.span())?;
+        self.bind(name, Value::Float(operation.floats(left, right)), span)
+    }
+
+    /*
+        Reads a line of input for `input`, which takes an integer, a float or else the line as a string,
+        `input_int` or `input_float`. Once the input has ended every one of them gives none.
+    */
+    fn read_input(&mut self, keyword: Lexeme, span: Span) -> Result<Value> {
+        let mut line = String::new();
+        if self.input_stream.read_line(&mut line)? == 0 {
+            return Ok(Value::None);
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        let number = line.trim();
+        match keyword {
+            Lexeme::InputInt => number.parse().map(Value::Int).map_err(|_| {
+                NekoError::at(
+                    format!("Expected an integer as input, found {}", line),
+                    span,
+                )
+            }),
+            Lexeme::InputFloat => number.parse().map(Value::Float).map_err(|_| {
+                NekoError::at(format!("Expected a float as input, found {}", line), span)
+            }),
+            _ => Ok(number
+                .parse()
+                .map(Value::Int)
+                .or_else(|_| number.parse().map(Value::Float))
+                .unwrap_or_else(|_| Value::Str(line.to_owned()))),
+        }
+    }
+
+    /*
+        Defines a constant, which may be declared again only with the same value
+    */
+    fn define_constant(&mut self, name: &str, value: Value, span: Span) -> Result<()> {
+        match self.constants.get(name) {
+            Some(existing) if *existing != value => Err(NekoError::at(
+                format!("Constant {} is already defined", name),
+                span,
+            )),
+            Some(_) => Ok(()),
+            None if self.lookup(name).is_some() => Err(NekoError::at(
+                format!("Cannot declare constant {} over a variable", name),
+                span,
+            )),
+            None => {
+                self.constants.insert(name.to_owned(), value);
+                Ok(())
+            }
+        }
+    }
+
+    fn print(&mut self, name: &str, span: Span) -> Result<()> {
+        let value = self
+            .lookup(name)
+            .ok_or_else(|| NekoError::at(format!("Unknown variable: {}", name), span))?;
+        value::write_value(&mut self.output_stream, name, &value)?;
+        Ok(())
+    }
+
+    /*
+        Builds the enum value for `call Type.Variant payload... into name`, leaving `into name` unread.
+        Returns `None` when `target` does not name a variant.
+    */
+    fn construct_variant(&self, target: &str, source: &mut Tokens) -> Result<Option<Value>> {
+        let Some((type_name, variant)) = target.split_once('.') else {
+            return Ok(None);
+        };
+        let Some(variants) = self.enum_types.get(type_name) else {
+            return Ok(None);
+        };
+        let span = source.span();
+        let fields = match variants.iter().find(|candidate| candidate.name == variant) {
+            Some(found) => &found.fields,
+            None => return Err(NekoError::at(format!("Unknown variant: {}", target), span)),
+        };
+        let mut payload = vec![];
+        for _ in fields {
+            let word = source.next().unwrap();
+            payload.push(self.read_operand(word, source.span())?);
+        }
+        if source.peek() != Some("into") {
+            return Err(NekoError::at(
+                format!("Expected into after {}", target),
+                span,
+            ));
+        }
+        Ok(Some(Value::Enum(EnumValue {
+            type_name: type_name.to_owned(),
+            variant: variant.to_owned(),
+            payload,
+        })))
+    }
+
+    /*
+        Runs the case of `case Variant bindings... code...` matching the variant of the enum value named
+        after `match`, with its payload bound to the names given after the variant. `case _` matches any variant.
+        Every variant must be covered by a case, so the whole match is read before any case runs.
+    */
+    fn execute_match(&mut self, source: &mut Tokens) -> Result<Flow> {
+        let name = source.next().unwrap();
+        let value = match self.lookup(name) {
+            Some(Value::Enum(value)) => value,
+            _ => {
+                return Err(NekoError::at(
+                    format!("match expects an enum value: {}", name),
+                    source.span(),
+                ))
+            }
+        };
+        let span = source.span();
+        let variants = match self.enum_types.get(&value.type_name) {
+            Some(variants) => variants,
+            None => {
+                return Err(NekoError::at(
+                    format!("Unknown enum: {}", value.type_name),
+                    span,
+                ))
+            }
+        };
+        let mut covered = vec![];
+        let mut matched = None;
+        while let Some(word) = source.next() {
+            match word {
+                "endmatch" => break,
+                "case" => {
+                    let variant = source.next().unwrap();
+                    let fields = if variant == "_" {
+                        covered.extend(variants.iter().map(|variant| variant.name.as_str()));
+                        0
+                    } else {
+                        match variants.iter().find(|candidate| candidate.name == variant) {
+                            Some(found) => {
+                                covered.push(found.name.as_str());
+                                found.fields.len()
                             }
-                            match word {
-                                "var" => {
-                                    let name = inner_source.next().unwrap();
-                                    let value = inner_source.next().unwrap().parse().unwrap();
-                                    self.variables.insert(name.to_owned(), value);
-                                }
-                                "print" => {
-                                    let name = inner_source.next().unwrap();
-                                    writeln!(self.output_stream, "{}", self.variables[name])?;
-                                }
-                                "add" => {
-                                    let name1 = inner_source.next().unwrap();
-                                    let name2 = inner_source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() +=
-                                        self.variables[name2];
-                                }
-                                "sub" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() -=
-                                        self.variables[name2];
-                                }
-                                "mul" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() *=
-                                        self.variables[name2];
-                                }
-                                "div" => {
-                                    let name1 = source.next().unwrap();
-                                    let name2 = source.next().unwrap();
-                                    *self.variables.get_mut(name1).unwrap() /=
-                                        self.variables[name2];
-                                }
-                                _ => {}
+                            None => {
+                                return Err(NekoError::at(
+                                    format!("Unknown variant of {}: {}", value.type_name, variant),
+                                    source.span(),
+                                ))
                             }
                         }
+                    };
+                    let bindings: Vec<&str> = source.by_ref().take(fields).collect();
+                    let body = source.clone();
+                    source.skip_block(&["case", "endmatch"]);
+                    if matched.is_none() && (variant == "_" || variant == value.variant) {
+                        matched = Some((bindings, body));
                     }
                 }
-                Lexeme::Add => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.variables[name1].add(self.variables[name2]);
-                    self.variables.insert(name1.to_owned(), result);
-                }
-                Lexeme::Sub => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.variables[name1].sub(self.variables[name2]);
-                    self.variables.insert(name1.to_owned(), result);
-                }
-                Lexeme::Mul => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.variables[name1].mul(self.variables[name2]);
-                    self.variables.insert(name1.to_owned(), result);
-                }
-                Lexeme::Div => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.variables[name1].div(self.variables[name2]);
-                    self.variables.insert(name1.to_owned(), result);
+                _ => {
+                    return Err(NekoError::at(
+                        format!("Expected case in match, found {}", word),
+                        source.span(),
+                    ))
                 }
-                Lexeme::AddF => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.float[name1].add(self.float[name2]);
-                    self.float.insert(name1.to_owned(), result);
-                }
-                Lexeme::SubF => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.float[name1].sub(self.float[name2]);
-                    self.float.insert(name1.to_owned(), result);
-                }
-                Lexeme::MulF => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.float[name1].mul(self.float[name2]);
-                    self.float.insert(name1.to_owned(), result);
-                }
-                Lexeme::DivF => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.float[name1].div(self.float[name2]);
-                    self.float.insert(name1.to_owned(), result);
-                }
-                Lexeme::Sqrt => {
-                    let name = source.next().unwrap();
-                    self.variables
-                        .insert(name.to_owned(), (self.variables[name] as f32).sqrt() as i32);
+            }
+        }
+        let missing: Vec<&str> = variants
+            .iter()
+            .map(|variant| variant.name.as_str())
+            .filter(|variant| !covered.contains(variant))
+            .collect();
+        if !missing.is_empty() {
+            return Err(NekoError::at(
+                format!(
+                    "Match on {} does not cover: {}",
+                    value.type_name,
+                    missing.join(", ")
+                ),
+                span,
+            ));
+        }
+
+        let (bindings, mut body) = matched.expect("every variant is covered");
+        for (name, payload) in bindings.into_iter().zip(value.payload) {
+            self.bind(name, payload, span)?;
+        }
+        self.execute_block(&mut body, &["case", "endmatch"])
+    }
+
+    /*
+        Reads `parameters... with code... end`, the part of a function or method definition after its name
+    */
+    fn read_function(&self, source: &mut Tokens, method: bool) -> Result<Function> {
+        let signature = types::read_signature(source);
+        let code = source.skip_block(&["end"]).to_vec();
+        source.next();
+        let constants = self.constants.keys().cloned().collect();
+        let chunk = Compiler::compile_function(&code, &signature, method, &constants)?;
+        let chunk = Rc::new(chunk);
+        Ok(Function {
+            signature,
+            code,
+            chunk,
+        })
+    }
+
+    /*
+        Runs a function in a scope of its own, `body` executing its code either way the interpreter runs.
+        For methods the receiver is bound to `self`, and written back once the body has run so the method can change it.
+    */
+    fn invoke(
+        &mut self,
+        function: &Function,
+        receiver: Option<(&str, Span)>,
+        arguments: Vec<Value>,
+        span: Option<Span>,
+        body: impl FnOnce(&mut Self) -> Result<Flow>,
+    ) -> Result<Option<Value>> {
+        let frame = self.enter(function, receiver, arguments, span)?;
+        let flow = body(self);
+        self.leave(frame, receiver, flow)
+    }
+
+    /*
+        The function a call runs, under the name it is defined with, along with the struct a method is called on
+    */
+    fn function_for<'a>(&self, target: &'a str, span: Span) -> Result<Callee<'a>> {
+        let (function_name, receiver) = match target.rsplit_once('.') {
+            Some((receiver, method)) => {
+                let type_name = match self.lookup_ref(receiver) {
+                    Some(Value::Struct(instance)) => &instance.type_name,
+                    _ => return Err(NekoError::at(format!("Unknown struct: {}", receiver), span)),
+                };
+                (format!("{}.{}", type_name, method), Some((receiver, span)))
+            }
+            None => (target.to_owned(), None),
+        };
+        match self.functions.get(&function_name) {
+            Some(function) => Ok((function.clone(), function_name, receiver)),
+            None => Err(NekoError::at(
+                format!("Unknown function: {}", function_name),
+                span,
+            )),
+        }
+    }
+
+    /*
+        Checks the value a call returned against the type its function declares
+    */
+    fn returned(
+        &self,
+        function: &Function,
+        function_name: &str,
+        result: Option<Value>,
+        span: Span,
+    ) -> Result<Option<Value>> {
+        if let (Some(annotation), Some(value)) = (&function.signature.returns, &result) {
+            let returned = format!("{} result", function_name);
+            self.check_annotation(&returned, Some(annotation), value, span)?;
+        }
+        Ok(result)
+    }
+
+    /*
+        Swaps in the scope of a call, binding its receiver and arguments, once it is known there is room for it
+    */
+    fn enter(
+        &mut self,
+        function: &Function,
+        receiver: Option<(&str, Span)>,
+        arguments: Vec<Value>,
+        span: Option<Span>,
+    ) -> Result<Frame> {
+        if self.call_depth >= self.max_call_depth {
+            let message = format!(
+                "Stack overflow: more than {} calls in progress",
+                self.max_call_depth
+            );
+            let error = match span {
+                Some(span) => NekoError::at(message, span),
+                None => NekoError::new(message),
+            };
+            return Err(error.with_kind(ErrorKind::StackOverflow));
+        }
+        let instance = receiver
+            .map(|(receiver, span)| {
+                self.lookup(receiver)
+                    .ok_or_else(|| NekoError::at(format!("Unknown struct: {}", receiver), span))
+            })
+            .transpose()?;
+        if let Some(span) = span {
+            let copies: usize = instance.iter().chain(&arguments).map(Value::size).sum();
+            self.reserve(FRAME_BYTES + copies, 0, span)?;
+        }
+        let scope = Scope::new(Rc::clone(&function.chunk.slots));
+        let caller_scope = std::mem::replace(&mut self.scope, scope);
+        let held = caller_scope.bytes + FRAME_BYTES;
+        self.held += held;
+        self.call_depth += 1;
+        if let Some(instance) = instance.clone() {
+            self.scope.set("self", instance);
+        }
+        for (parameter, argument) in function.signature.parameters.iter().zip(arguments) {
+            self.scope.set(&parameter.name, argument);
+        }
+        Ok(Frame {
+            caller_scope,
+            held,
+            instance,
+        })
+    }
+
+    /*
+        Puts back the scope of the caller once a call has run, handing a changed receiver back to it
+    */
+    fn leave(
+        &mut self,
+        frame: Frame,
+        receiver: Option<(&str, Span)>,
+        flow: Result<Flow>,
+    ) -> Result<Option<Value>> {
+        let Frame {
+            caller_scope,
+            held,
+            instance,
+        } = frame;
+        let changed = match self.scope.remove("self") {
+            Some(Value::Struct(instance)) => Some(Value::Struct(instance)),
+            _ => None,
+        };
+        self.scope = caller_scope;
+        self.held -= held;
+        self.call_depth -= 1;
+        if let (Some((receiver, span)), Some(changed)) = (receiver, changed) {
+            if instance.as_ref() != Some(&changed) {
+                self.bind(receiver, changed, span)?;
+            }
+        }
+
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            Flow::Next => Ok(None),
+            Flow::Break | Flow::Continue => Err(NekoError::new(format!(
+                "break or continue outside of a loop in {}",
+                receiver.map_or("function", |(receiver, _)| receiver)
+            ))),
+        }
+    }
+
+    /*
+        Binds a value to a single name, or spreads a tuple over as many names as it has elements
+    */
+    fn bind_all(&mut self, names: &[&str], value: Value, span: Span) -> Result<()> {
+        match (names, value) {
+            ([name], value) => self.bind(name, value, span),
+            (names, Value::Tuple(values)) if names.len() == values.len() => {
+                for (name, value) in names.iter().zip(values) {
+                    self.bind(name, value, span)?;
                 }
-                Lexeme::ABS => {
-                    let name = source.next().unwrap();
-                    self.variables
-                        .insert(name.to_owned(), self.variables[name].abs());
+                Ok(())
+            }
+            (names, value) => Err(NekoError::at(
+                format!("Cannot bind {} to {} names", value, names.len()),
+                span,
+            )),
+        }
+    }
+
+    /*
+        Binds a value to a name, or to a struct field or map entry when given a dotted path,
+        replacing whatever the name was bound to before. A path to a key a map does not have
+        adds the key. Constants can not be rebound.
+    */
+    fn bind(&mut self, name: &str, value: Value, span: Span) -> Result<()> {
+        self.check_mutable(name, span)?;
+        let Some((parent, key)) = name.rsplit_once('.') else {
+            return self.bind_local(name, value, span);
+        };
+        let mut added = value.size();
+        let replaced = match self.lookup_path_mut(name).map(|existing| existing.size()) {
+            Some(size) => size,
+            None if matches!(self.lookup_ref(parent), Some(Value::Map(_))) => {
+                added += key.len();
+                0
+            }
+            None => return Err(NekoError::at(format!("Unknown field: {}", name), span)),
+        };
+        self.reserve(added, replaced, span)?;
+        match self.lookup_path_mut(name) {
+            Some(existing) => *existing = value,
+            None => {
+                if let Some(Value::Map(entries)) = self.lookup_mut(parent) {
+                    entries.insert(key.to_owned(), value);
                 }
-                Lexeme::POW => {
-                    let name1 = source.next().unwrap();
-                    let name2 = source.next().unwrap();
-                    let result = self.variables[name1].pow(self.variables[name2] as u32);
-                    self.variables.insert(name1.to_owned(), result);
+            }
+        }
+        self.scope.bytes = self.scope.bytes + added - replaced;
+        Ok(())
+    }
+
+    /*
+        Fails when a value does not have the type `name` was annotated with
+    */
+    fn check_annotation(
+        &self,
+        name: &str,
+        annotation: Option<&str>,
+        value: &Value,
+        span: Span,
+    ) -> Result<()> {
+        let value = Type::of(value);
+        match annotation {
+            Some(annotation) if !value.fits(annotation) => Err(NekoError::at(
+                format!(
+                    "{} is declared {} but given {}",
+                    name,
+                    annotation,
+                    value.name()
+                ),
+                span,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+        Fails when `name`, or the struct a dotted path starts from, is a constant
+    */
+    fn check_mutable(&self, name: &str, span: Span) -> Result<()> {
+        let root = name.split('.').next().unwrap_or(name);
+        if !self.constants.is_empty() && self.constants.contains_key(root) {
+            return Err(NekoError::at(
+                format!("Cannot assign to constant {}", root),
+                span,
+            ));
+        }
+        Ok(())
+    }
+
+    /*
+        Binds a value to a plain name in the current scope, without the checks `bind` makes
+    */
+    fn bind_local(&mut self, name: &str, value: Value, span: Span) -> Result<()> {
+        self.reserve(value.size(), self.scope.size(name), span)?;
+        self.scope.set(name, value);
+        Ok(())
+    }
+
+    /*
+        Fails at `span` when holding `added` more bytes in place of `removed` ones would take
+        the variables and calls in progress past the memory limit
+    */
+    fn reserve(&self, added: usize, removed: usize, span: Span) -> Result<()> {
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        let needed = self.memory_used().saturating_add(added) - removed;
+        if needed > limit {
+            return Err(NekoError::at(
+                format!(
+                    "Out of memory: {} bytes needed, the limit is {}",
+                    needed, limit
+                ),
+                span,
+            )
+            .with_kind(ErrorKind::OutOfMemory));
+        }
+        Ok(())
+    }
+
+    /*
+        Reads the struct declared after `struct name`, up to the closing `endstruct`.
+        `as Type` starts from a copy of the struct named `Type` and gives the new struct its methods.
+        A field value is an integer or float literal, an inline `string ... endstring`,
+        `array size elements...` or `struct ... endstruct`,
+        or the name of an existing binding whose value is copied into the field.
+    */
+    fn read_struct(&self, name: &str, source: &mut Tokens) -> Result<Struct> {
+        let mut instance = Struct {
+            type_name: name.to_owned(),
+            fields: Fields::new(),
+        };
+        if source.peek() == Some("as") {
+            source.next();
+            let type_name = source.next().unwrap();
+            if let Some(Value::Struct(prototype)) = self.scope.get(type_name) {
+                instance.fields = prototype.fields.clone();
+            }
+            instance.type_name = type_name.to_owned();
+        }
+        while let Some(field) = source.next() {
+            if field == "endstruct" {
+                break;
+            }
+            let value = self.read_value(field, source)?;
+            instance.fields.insert(field.to_owned(), value);
+        }
+        Ok(instance)
+    }
+
+    fn read_value(&self, name: &str, source: &mut Tokens) -> Result<Value> {
+        let word = source.next().unwrap();
+        let value = match word {
+            "struct" => Value::Struct(self.read_struct(name, source)?),
+            "string" => {
+                let words: Vec<&str> = source
+                    .by_ref()
+                    .take_while(|&word| word != "endstring")
+                    .collect();
+                Value::Str(words.join(" "))
+            }
+            _ if Lexeme::parse(word) == Some(Lexeme::Array) => {
+                Value::Array(self.read_array(name, word, source)?)
+            }
+            _ => self.read_operand(word, source.span())?,
+        };
+        Ok(value)
+    }
+
+    /*
+        Reads `size elements...`, the part of an array declaration after its name. An element is
+        an operand or an inline `struct ... endstruct`. When the keyword names an element type,
+        as in `array<float>`, every element must have that type.
+    */
+    fn read_array(&self, name: &str, keyword: &str, source: &mut Tokens) -> Result<Vec<Value>> {
+        let size = source.next().unwrap().parse().unwrap();
+        let mut array = Vec::with_capacity(size);
+        for _ in 0..size {
+            let word = source.next().unwrap();
+            let span = source.span();
+            let value = match word {
+                "struct" => Value::Struct(self.read_struct(name, source)?),
+                _ => self.read_operand(word, span)?,
+            };
+            let value = match lexeme::element_type(keyword) {
+                Some(element_type) => {
+                    types::element(value.clone(), element_type).ok_or_else(|| {
+                        NekoError::at(
+                            format!(
+                                "Expected {} elements in {}, found {}",
+                                element_type, name, value
+                            ),
+                            span,
+                        )
+                    })?
                 }
-                Lexeme::End => {}
-                _ => panic!("Unknown command: {}", word),
+                None => value,
+            };
+            array.push(value);
+        }
+        Ok(array)
+    }
+
+    /*
+        The tuple a literal read by `next_tuple` stands for
+    */
+    fn read_tuple(&self, elements: &[TupleElement], span: Span) -> Result<Value> {
+        let values = elements
+            .iter()
+            .map(|element| match element {
+                TupleElement::Word(word) => self.read_operand(word, span),
+                TupleElement::Tuple(elements) => self.read_tuple(elements, span),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Value::Tuple(values))
+    }
+
+    /*
+        An operand is an integer, float or quoted string literal, or the name of a binding or struct field
+    */
+    fn read_operand(&self, word: &str, span: Span) -> Result<Value> {
+        self.operand(word)
+            .ok_or_else(|| self.unknown_value(word, span))
+    }
+
+    fn operand(&self, word: &str) -> Option<Value> {
+        if let Some(text) = lexeme::unquote(word) {
+            Some(Value::Str(text))
+        } else if word == "none" {
+            Some(Value::None)
+        } else if let Some(value) = lexeme::boolean(word) {
+            Some(Value::Int(value))
+        } else if let Ok(value) = word.parse() {
+            Some(Value::Int(value))
+        } else if let Ok(value) = word.parse() {
+            Some(Value::Float(value))
+        } else {
+            self.lookup(word)
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
+        self.lookup_ref(name).cloned()
+    }
+
+    fn lookup_ref(&self, name: &str) -> Option<&Value> {
+        if name.contains('.') {
+            return self.lookup_path(name);
+        }
+        self.scope.get(name).or_else(|| self.constants.get(name))
+    }
+
+    /*
+        The error for an operand that names nothing, telling a missing field apart from a missing binding
+    */
+    fn unknown_value(&self, word: &str, span: Span) -> NekoError {
+        match word.split_once('.') {
+            Some((root, _)) if self.lookup(root).is_some() => {
+                NekoError::at(format!("Unknown field: {}", word), span)
             }
+            _ => NekoError::at(format!("Unknown value: {}", word), span),
         }
+    }
 
-        Ok(self.output_stream.by_ref())
+    /*
+        An integer or float operand, as compared by `if` and `loop`
+    */
+    fn number(&self, word: &str, span: Span) -> Result<Value> {
+        match self.operand(word) {
+            Some(value @ (Value::Int(_) | Value::Float(_))) => Ok(value),
+            Some(other) => Err(not_a("a number", word, other, span)),
+            None => Err(self.unknown_value(word, span)),
+        }
     }
-    pub fn call_function(&mut self, name: &str, parameters: &[i32]) -> Result<()> {
-        let function = self.functions.get(name).unwrap();
-        let mut interpreter = Interpreter::new();
-        for (param_name, param_value) in function.parameters.iter().zip(parameters) {
-            interpreter
-                .variables
-                .insert(param_name.to_owned(), *param_value);
+
+    /*
+        An integer operand: a literal, or an integer variable, constant or struct field
+    */
+    fn integer(&self, word: &str, span: Span) -> Result<i32> {
+        match self.operand(word) {
+            Some(Value::Int(value)) => Ok(value),
+            Some(other) => Err(not_a("an integer", word, other, span)),
+            None => Err(self.unknown_value(word, span)),
         }
-        interpreter.run(&function.code.join(" "))?;
+    }
+
+    /*
+        A float operand: a literal, or a float variable, constant or struct field
+    */
+    fn real(&self, word: &str, span: Span) -> Result<f32> {
+        match self.operand(word) {
+            Some(Value::Float(value)) => Ok(value),
+            Some(other) => Err(not_a("a float", word, other, span)),
+            None => Err(self.unknown_value(word, span)),
+        }
+    }
+
+    /*
+        Resolves a dotted path such as `line.start.x`, `ages.bob` or `pair.0`
+        into the struct field, map entry or tuple element it names
+    */
+    fn lookup_path(&self, path: &str) -> Option<&Value> {
+        let mut parts = path.split('.');
+        let root = parts.next()?;
+        let first = parts.next()?;
+        let mut value = match self.scope.get(root) {
+            Some(Value::Map(entries)) => entries.get(first).unwrap_or(&Value::None),
+            Some(value) => value.field(first)?,
+            None => self.constants.get(root)?.field(first)?,
+        };
+        for part in parts {
+            value = value.field(part)?;
+        }
+        Some(value)
+    }
+
+    /*
+        The variable, or the field or entry a dotted path names, so it can be changed
+    */
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Value> {
+        if name.contains('.') {
+            return self.lookup_path_mut(name);
+        }
+        self.scope.get_mut(name)
+    }
+
+    /*
+        Resolves a path such as `line.start.x` or `ages.bob` into the field or entry it names, so it can be changed
+    */
+    fn lookup_path_mut(&mut self, path: &str) -> Option<&mut Value> {
+        let mut parts = path.split('.');
+        let root = parts.next()?;
+        let first = parts.next()?;
+        let mut value = self.scope.get_mut(root)?.field_mut(first)?;
+        for part in parts {
+            value = value.field_mut(part)?;
+        }
+        Some(value)
+    }
+
+    pub fn call_function(&mut self, name: &str, parameters: &[i32]) -> Result<()> {
+        let function = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| NekoError::new(format!("Unknown function: {}", name)))?;
+        let arguments = parameters.iter().map(|&value| Value::Int(value)).collect();
+        let chunk = Rc::clone(&function.chunk);
+        self.finishing(|this| {
+            this.start();
+            this.invoke(&function, None, arguments, None, |this| {
+                this.execute_chunk(&chunk)
+            })
+        })?;
 
         Ok(())
     }
+
+    /*
+        The value of a variable, constant or field path such as `point.x`
+    */
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.lookup(name)
+    }
+
+    /*
+        The integer a variable, constant or field path holds, or nothing when it holds something else
+    */
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        match self.lookup_ref(name)? {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.lookup_ref(name)? {
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.lookup_ref(name)? {
+            Value::Str(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<&[Value]> {
+        match self.lookup_ref(name)? {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn get_struct(&self, name: &str) -> Option<&Struct> {
+        match self.lookup_ref(name)? {
+            Value::Struct(instance) => Some(instance),
+            _ => None,
+        }
+    }
+
+    /*
+        Binds a value to a variable, or to the field a path such as `point.x` names,
+        as `set` does in a program. Programs run afterwards see it like any other variable.
+        Fails for constants and missing fields, and when the memory limit would be exceeded.
+    */
+    pub fn set(&mut self, name: &str, value: impl Into<Value>) -> Result<()> {
+        self.bind(name, value.into(), Span::default())
+            .map_err(|error| NekoError {
+                span: None,
+                ..error
+            })
+    }
+
+    pub fn set_int(&mut self, name: &str, value: i32) -> Result<()> {
+        self.set(name, value)
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) -> Result<()> {
+        self.set(name, value)
+    }
+
+    pub fn set_str(&mut self, name: &str, text: &str) -> Result<()> {
+        self.set(name, text)
+    }
+
+    pub fn set_array(&mut self, name: &str, values: Vec<Value>) -> Result<()> {
+        self.set(name, values)
+    }
+
+    pub fn set_struct(&mut self, name: &str, instance: Struct) -> Result<()> {
+        self.set(name, Value::Struct(instance))
+    }
+
+    /*
+        Every variable with its value, in the order they were first bound
+    */
+    pub fn iter_bindings(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.scope
+            .bindings()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /*
+        Every variable with its value, sorted by name
+    */
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut bindings: Vec<(String, Value)> = self
+            .scope
+            .bindings()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|(left, _), (right, _)| left.cmp(right));
+        bindings
+    }
+
+    /*
+        Every constant with its value, sorted by name
+    */
+    pub fn constants(&self) -> Vec<(String, Value)> {
+        let mut constants: Vec<(String, Value)> = self
+            .constants
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        constants.sort_by(|(left, _), (right, _)| left.cmp(right));
+        constants
+    }
+
+    /*
+        Every function and method with its signature, sorted by name. Methods are named `Type.method`
+    */
+    pub fn functions(&self) -> Vec<(String, Signature)> {
+        let mut functions: Vec<(String, Signature)> = self
+            .functions
+            .iter()
+            .map(|(name, function)| (name.clone(), function.signature.clone()))
+            .collect();
+        functions.sort_by(|(left, _), (right, _)| left.cmp(right));
+        functions
+    }
+
+    /*
+        Evaluates an expression such as `x * 2 + 1` against the current variables,
+        the way placeholders of a formatted print are evaluated
+    */
+    pub fn evaluate(&self, expression: &str) -> Result<Value> {
+        format::evaluate(expression, &|name| self.lookup(name))
+    }
+
+    /*
+        Forgets every variable, constant, function and type, keeping the output stream
+        and the functions registered from Rust
+    */
+    pub fn reset(&mut self) {
+        self.scope = Scope::default();
+        self.functions.clear();
+        self.enum_types.clear();
+        self.constants.clear();
+    }
+
+    pub fn output_stream(&mut self) -> &mut T {
+        &mut self.output_stream
+    }
+}
+
+/*
+    Compares two numbers, as floats when either of them is one
+*/
+pub(crate) fn compare(comparison: &Comparison, left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(left), Value::Int(right)) => comparison.holds(left, right),
+        (Value::Int(left), Value::Float(right)) => comparison.holds(*left as f32, *right),
+        (Value::Float(left), Value::Int(right)) => comparison.holds(*left, *right as f32),
+        (Value::Float(left), Value::Float(right)) => comparison.holds(left, right),
+        _ => false,
+    }
+}
+
+/*
+    Calls a function registered by the host, pointing the errors it raises without a position at the call
+*/
+fn call_native(
+    name: &str,
+    native: &NativeFunction,
+    arguments: &[Value],
+    span: Span,
+) -> Result<Value> {
+    native(arguments).map_err(|error| match error.span {
+        Some(_) => error,
+        None => NekoError::at(format!("{}: {}", name, error.message), span).with_kind(error.kind),
+    })
+}
+
+/*
+    The error for an operand that does not hold the `expected` kind of value
+*/
+fn not_a(expected: &str, word: &str, value: Value, span: Span) -> NekoError {
+    match value {
+        Value::None => NekoError::at(
+            format!("Cannot use none in arithmetic: {} is none", word),
+            span,
+        ),
+        other => NekoError::at(format!("Expected {}, found {}", expected, other), span),
+    }
+}
+
+/*
+    The pairs `foreach` walks: index and element of an array, tuple or string, key and value of a map,
+    name and value of a struct field
+*/
+fn entries(collection: Value, span: Span) -> Result<Vec<(Value, Value)>> {
+    let entries = match collection {
+        Value::Array(values) | Value::Tuple(values) => values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| (Value::Int(index as i32), value))
+            .collect(),
+        Value::Str(text) => text
+            .chars()
+            .enumerate()
+            .map(|(index, c)| (Value::Int(index as i32), Value::Str(c.to_string())))
+            .collect(),
+        Value::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| (Value::Str(key.clone()), value.clone()))
+            .collect(),
+        Value::Struct(instance) => instance
+            .fields
+            .iter()
+            .map(|(field, value)| (Value::Str(field.clone()), value.clone()))
+            .collect(),
+        other => {
+            return Err(NekoError::at(
+                format!("Cannot iterate over {}", other),
+                span,
+            ))
+        }
+    };
+    Ok(entries)
 }
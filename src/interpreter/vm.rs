@@ -0,0 +1,601 @@
+use super::{call_native, compare, entries, not_a, Flow, Function, Interpreter};
+use crate::builtins::{self, Argument};
+use crate::bytecode::{
+    Arithmetic, ArithmeticSite, CallSite, Chunk, Iteration, MatchTable, Op, Operand, Place,
+};
+use crate::error::{NekoError, Result};
+use crate::format;
+use crate::lexeme::Span;
+use crate::types;
+use crate::value::{EnumValue, Fields, Struct, Value};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+impl<T: Write, R: BufRead> Interpreter<T, R> {
+    /*
+        Runs compiled code on a stack of its own, sharing the bindings of the interpreter with the
+        tree-walking path. Ends with the flow that left the code, as `execute_block` does.
+    */
+    pub(super) fn execute_chunk(&mut self, chunk: &Chunk) -> Result<Flow> {
+        let mut stack: Vec<Value> = vec![];
+        let mut ip = 0;
+        while let Some(op) = chunk.code.get(ip) {
+            let span = chunk.spans[ip];
+            ip += 1;
+            self.step(span)?;
+            match op {
+                Op::Call(call) => {
+                    let call = &chunk.calls[*call];
+                    let arguments = stack.split_off(stack.len() - call.arguments.len());
+                    self.call(chunk, call, arguments, span)?;
+                }
+                Op::Return(count) => {
+                    let mut values = stack.split_off(stack.len() - count);
+                    let value = match values.len() {
+                        0 => None,
+                        1 => values.pop(),
+                        _ => Some(Value::Tuple(values)),
+                    };
+                    return Ok(Flow::Return(value));
+                }
+                Op::Jump(target) => ip = *target,
+                Op::JumpUnless { comparison, target } => {
+                    let left = pop(&mut stack);
+                    let right = pop(&mut stack);
+                    if !compare(comparison, &left, &right) {
+                        ip = *target;
+                    }
+                }
+                Op::LoopUnless {
+                    comparison,
+                    operand,
+                    target,
+                } => {
+                    let left = match self.load(chunk, *operand, span)? {
+                        value @ (Value::Int(_) | Value::Float(_)) => value,
+                        other => return Err(not_a("a number", &chunk.word(*operand), other, span)),
+                    };
+                    let right = stack.last().expect("loop without a bound");
+                    if !compare(comparison, &left, right) {
+                        ip = *target;
+                    }
+                }
+                Op::Next(iteration) => {
+                    let iteration = &chunk.iterations[*iteration];
+                    if let Some(exit) = self.next_entry(chunk, iteration, &mut stack, span)? {
+                        ip = exit;
+                    }
+                }
+                Op::Switch(table) => {
+                    let table = &chunk.switches[*table];
+                    let value = integer(pop(&mut stack));
+                    ip = table
+                        .cases
+                        .iter()
+                        .find(|&&(case, _)| case == value)
+                        .map_or(table.end, |&(_, target)| target);
+                }
+                Op::Match(table) => ip = self.match_case(chunk, &chunk.matches[*table], span)?,
+                Op::Break => return Ok(Flow::Break),
+                Op::Continue => return Ok(Flow::Continue),
+                Op::OutsideLoop => {
+                    return Err(NekoError::at("break or continue outside of a loop", span))
+                }
+                op => self.execute_op(chunk, op, &mut stack, span)?,
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /*
+        Runs an instruction that neither jumps nor calls. Keeping these out of `execute_chunk` keeps
+        its frame small, since every call a program makes adds one to the native stack.
+    */
+    fn execute_op(
+        &mut self,
+        chunk: &Chunk,
+        op: &Op,
+        stack: &mut Vec<Value>,
+        span: Span,
+    ) -> Result<()> {
+        match op {
+            Op::Push(operand) => stack.push(self.load(chunk, *operand, span)?),
+            Op::Int(operand) => match self.load(chunk, *operand, span)? {
+                value @ Value::Int(_) => stack.push(value),
+                other => return Err(not_a("an integer", &chunk.word(*operand), other, span)),
+            },
+            Op::Float(operand) => match self.load(chunk, *operand, span)? {
+                value @ Value::Float(_) => stack.push(value),
+                other => return Err(not_a("a float", &chunk.word(*operand), other, span)),
+            },
+            Op::Number(operand) => match self.load(chunk, *operand, span)? {
+                value @ (Value::Int(_) | Value::Float(_)) => stack.push(value),
+                other => return Err(not_a("a number", &chunk.word(*operand), other, span)),
+            },
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::Store(place) => {
+                let value = pop(stack);
+                self.store(chunk, *place, value, span)?;
+            }
+            Op::StoreLocal(Place::Slot(slot)) => {
+                let value = pop(stack);
+                self.store_slot(*slot, value, span)?;
+            }
+            Op::StoreLocal(Place::Name(name)) => {
+                let name = &chunk.names[*name];
+                self.check_mutable(name, span)?;
+                let value = pop(stack);
+                self.bind_local(name, value, span)?;
+            }
+            Op::Annotate { name, annotation } => {
+                let value = stack.last().expect("annotate on an empty stack");
+                let annotation = Some(chunk.names[*annotation].as_str());
+                self.check_annotation(&chunk.names[*name], annotation, value, span)?;
+            }
+            Op::Element {
+                array,
+                element_type,
+            } => {
+                let value = pop(stack);
+                let element_type = &chunk.names[*element_type];
+                let element = types::element(value.clone(), element_type).ok_or_else(|| {
+                    NekoError::at(
+                        format!(
+                            "Expected {} elements in {}, found {}",
+                            element_type, chunk.names[*array], value
+                        ),
+                        span,
+                    )
+                })?;
+                stack.push(element);
+            }
+            Op::MakeArray(size) => {
+                let values = stack.split_off(stack.len() - size);
+                stack.push(Value::Array(values));
+            }
+            Op::MakeTuple(size) => {
+                let values = stack.split_off(stack.len() - size);
+                stack.push(Value::Tuple(values));
+            }
+            Op::MakeStruct(layout) => {
+                let layout = &chunk.layouts[*layout];
+                let values = stack.split_off(stack.len() - layout.fields.len());
+                let mut fields = match &layout.prototype {
+                    Some(prototype) => match self.scope.get(prototype) {
+                        Some(Value::Struct(prototype)) => prototype.fields.clone(),
+                        _ => Fields::new(),
+                    },
+                    None => Fields::new(),
+                };
+                for (field, value) in layout.fields.iter().zip(values) {
+                    fields.insert(field.clone(), value);
+                }
+                stack.push(Value::Struct(Struct {
+                    type_name: layout.type_name.clone(),
+                    fields,
+                }));
+            }
+            Op::MakeMap(layout) => {
+                let keys = &chunk.layouts[*layout].fields;
+                let values = stack.split_off(stack.len() - keys.len());
+                let mut entries = Fields::new();
+                for (key, value) in keys.iter().zip(values) {
+                    entries.insert(key.clone(), value);
+                }
+                stack.push(Value::Map(entries));
+            }
+            Op::DefineConstant(name) => {
+                let value = pop(stack);
+                self.define_constant(&chunk.names[*name], value, span)?;
+            }
+            Op::DefineFunction(index) => {
+                let (name, function) = &chunk.functions[*index];
+                self.functions.insert(name.clone(), function.clone());
+            }
+            Op::DefineEnum(index) => {
+                let (name, variants) = &chunk.enums[*index];
+                self.enum_types.insert(name.clone(), variants.clone());
+            }
+            Op::Arithmetic(site) => self.arithmetic(chunk, &chunk.arithmetic[*site], span)?,
+            Op::SqrtInt => {
+                let value = integer(pop(stack));
+                stack.push(Value::Int((value as f32).sqrt() as i32));
+            }
+            Op::AbsInt => {
+                let value = integer(pop(stack)).checked_abs();
+                let value = value.ok_or_else(|| NekoError::at("Integer overflow in abs", span))?;
+                stack.push(Value::Int(value));
+            }
+            Op::Builtin { keyword, call } => {
+                let spans = &chunk.builtins[*call];
+                let values = stack.split_off(stack.len() - spans.len());
+                let arguments: Vec<Argument> = values
+                    .into_iter()
+                    .zip(spans)
+                    .map(|(value, &span)| Argument { value, span })
+                    .collect();
+                self.reserve(builtins::allocation(*keyword, &arguments), 0, span)?;
+                stack.push(builtins::call(*keyword, &arguments)?);
+            }
+            Op::PopArray(name) => {
+                let name = &chunk.names[*name];
+                self.check_mutable(name, span)?;
+                let value = match self.scope.get_mut(name) {
+                    Some(Value::Array(values)) => values.pop(),
+                    _ => {
+                        return Err(NekoError::at(
+                            format!("pop expects an array variable: {}", name),
+                            span,
+                        ))
+                    }
+                };
+                self.scope.bytes -= value.as_ref().map_or(0, Value::size);
+                stack.push(value.unwrap_or(Value::None));
+            }
+            Op::Input(keyword) => stack.push(self.read_input(*keyword, span)?),
+            Op::Print(name) => self.print(&chunk.names[*name], span)?,
+            Op::PrintFormat(template) => {
+                let Value::Str(template) = &chunk.constants[*template] else {
+                    unreachable!("print templates are strings")
+                };
+                let line = format::interpolate(template, &|name| self.lookup(name))
+                    .map_err(|error| NekoError::at(error.message, span))?;
+                writeln!(self.output_stream, "{}", line)?;
+            }
+            Op::Iterate(operand) => {
+                let collection = self.load(chunk, *operand, span).map_err(|_| {
+                    NekoError::at(format!("Unknown variable: {}", chunk.word(*operand)), span)
+                })?;
+                let entries = entries(collection, span)?
+                    .into_iter()
+                    .map(|(key, item)| Value::Tuple(vec![key, item]))
+                    .collect();
+                stack.push(Value::Array(entries));
+                stack.push(Value::Int(0));
+            }
+            _ => unreachable!("{:?} is run by execute_chunk", op),
+        }
+        Ok(())
+    }
+
+    /*
+        Binds the next entry of a `foreach`, or gives where the loop exits once there is none left
+    */
+    fn next_entry(
+        &mut self,
+        chunk: &Chunk,
+        iteration: &Iteration,
+        stack: &mut [Value],
+        span: Span,
+    ) -> Result<Option<usize>> {
+        let position = stack.len() - 1;
+        let Value::Int(index) = stack[position] else {
+            unreachable!("foreach keeps its position on the stack")
+        };
+        let Value::Array(entries) = &stack[position - 1] else {
+            unreachable!("foreach keeps its entries on the stack")
+        };
+        let Some(Value::Tuple(entry)) = entries.get(index as usize) else {
+            return Ok(Some(iteration.exit));
+        };
+        let (key, item) = (entry[0].clone(), entry[1].clone());
+        stack[position] = Value::Int(index + 1);
+        if let Some(place) = iteration.key {
+            self.store(chunk, place, key, span)?;
+        }
+        self.store(chunk, iteration.item, item, span)?;
+        Ok(None)
+    }
+
+    /*
+        Binds the payload of an enum value to the case of a `match` that covers it, giving where that case starts
+    */
+    fn match_case(&mut self, chunk: &Chunk, table: &MatchTable, span: Span) -> Result<usize> {
+        let value = match self.lookup(&table.name) {
+            Some(Value::Enum(value)) => value,
+            _ => {
+                return Err(NekoError::at(
+                    format!("match expects an enum value: {}", table.name),
+                    span,
+                ))
+            }
+        };
+        let variants = self
+            .enum_types
+            .get(&value.type_name)
+            .ok_or_else(|| NekoError::at(format!("Unknown enum: {}", value.type_name), span))?;
+        if let Some(case) = table.cases.iter().find(|case| {
+            case.variant != "_" && !variants.iter().any(|variant| variant.name == case.variant)
+        }) {
+            return Err(NekoError::at(
+                format!("Unknown variant of {}: {}", value.type_name, case.variant),
+                span,
+            ));
+        }
+        let missing: Vec<&str> = variants
+            .iter()
+            .map(|variant| variant.name.as_str())
+            .filter(|&variant| {
+                !table
+                    .cases
+                    .iter()
+                    .any(|case| case.variant == "_" || case.variant == variant)
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(NekoError::at(
+                format!(
+                    "Match on {} does not cover: {}",
+                    value.type_name,
+                    missing.join(", ")
+                ),
+                span,
+            ));
+        }
+        let case = table
+            .cases
+            .iter()
+            .find(|case| case.variant == "_" || case.variant == value.variant)
+            .expect("every variant is covered");
+        for (place, payload) in case.bindings.iter().zip(value.payload) {
+            self.store(chunk, *place, payload, span)?;
+        }
+        Ok(case.target)
+    }
+
+    /*
+        The value of an operand: a constant, the value in a slot, or the field or constant a name refers to
+    */
+    fn load(&self, chunk: &Chunk, operand: Operand, span: Span) -> Result<Value> {
+        match operand {
+            Operand::Constant(index) => Ok(chunk.constants[index].clone()),
+            Operand::Slot(slot) => match &self.scope.values[slot] {
+                Some(value) => Ok(value.clone()),
+                None => Err(self.unknown_value(chunk.slots.name(slot), span)),
+            },
+            Operand::Name(index) => {
+                let name = &chunk.names[index];
+                self.lookup(name)
+                    .ok_or_else(|| self.unknown_value(name, span))
+            }
+        }
+    }
+
+    /*
+        Binds a value to a place: straight into its slot, or through `bind` for a field path or constant
+    */
+    fn store(&mut self, chunk: &Chunk, place: Place, value: Value, span: Span) -> Result<()> {
+        match place {
+            Place::Slot(slot) => self.store_slot(slot, value, span),
+            Place::Name(name) => self.bind(&chunk.names[name], value, span),
+        }
+    }
+
+    fn store_slot(&mut self, slot: usize, value: Value, span: Span) -> Result<()> {
+        let replaced = self.scope.values[slot].as_ref().map_or(0, Value::size);
+        self.reserve(value.size(), replaced, span)?;
+        self.scope.put(slot, value);
+        Ok(())
+    }
+
+    /*
+        Stores a value in a single place, or spreads a tuple over as many places as it has elements
+    */
+    fn store_all(
+        &mut self,
+        chunk: &Chunk,
+        places: &[Place],
+        value: Value,
+        span: Span,
+    ) -> Result<()> {
+        match (places, value) {
+            ([place], value) => self.store(chunk, *place, value, span),
+            (places, Value::Tuple(values)) if places.len() == values.len() => {
+                for (place, value) in places.iter().zip(values) {
+                    self.store(chunk, *place, value, span)?;
+                }
+                Ok(())
+            }
+            (places, value) => Err(NekoError::at(
+                format!("Cannot bind {} to {} names", value, places.len()),
+                span,
+            )),
+        }
+    }
+
+    /*
+        Runs an arithmetic statement. A target whose slot holds a number of the right type is changed
+        in place; anything else is read and stored again, which checks that it may be assigned.
+    */
+    fn arithmetic(&mut self, chunk: &Chunk, site: &ArithmeticSite, span: Span) -> Result<()> {
+        let right = self.load(chunk, site.operand, site.operand_span);
+        let right_word = || chunk.word(site.operand);
+        let target = chunk.place(site.target);
+        let operation = site.operation;
+        let in_place = match site.target {
+            Place::Slot(slot) => self.scope.values[slot].as_mut(),
+            Place::Name(_) => None,
+        };
+        if operation.on_floats() {
+            if let Some(Value::Float(left)) = in_place {
+                match right? {
+                    Value::Float(right) => *left = operation.floats(*left, right),
+                    other => return Err(not_a("a float", &right_word(), other, site.operand_span)),
+                }
+                return Ok(());
+            }
+            let left = match self.load(chunk, site.target.into(), span)? {
+                Value::Float(left) => left,
+                other => return Err(not_a("a float", target, other, span)),
+            };
+            let right = match right? {
+                Value::Float(right) => right,
+                other => return Err(not_a("a float", &right_word(), other, site.operand_span)),
+            };
+            let result = Value::Float(operation.floats(left, right));
+            return self.store(chunk, site.target, result, span);
+        }
+        let integer = |value: Result<Value>| match value? {
+            Value::Int(0) if operation == Arithmetic::Div => {
+                Err(NekoError::at("Division by zero", site.operand_span))
+            }
+            Value::Int(value) => Ok(value),
+            other => Err(not_a("an integer", &right_word(), other, site.operand_span)),
+        };
+        if let Some(Value::Int(left)) = in_place {
+            *left = operation
+                .integers(*left, integer(right)?)
+                .map_err(|error| NekoError::at(error.message, span))?;
+            return Ok(());
+        }
+        let left = match self.load(chunk, site.target.into(), span)? {
+            Value::Int(left) => left,
+            other => return Err(not_a("an integer", target, other, span)),
+        };
+        let right = integer(right)?;
+        let result = operation
+            .integers(left, right)
+            .map_err(|error| NekoError::at(error.message, span))?;
+        let result = Value::Int(result);
+        self.store(chunk, site.target, result, span)
+    }
+
+    /*
+        Runs a `call`: builds an enum variant, or calls a function or a method on a struct,
+        and binds the result to the names after `into`
+    */
+    fn call(
+        &mut self,
+        chunk: &Chunk,
+        call: &CallSite,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<()> {
+        let target = call.target.as_str();
+        let constructs = target
+            .split_once('.')
+            .is_some_and(|(type_name, _)| self.enum_types.contains_key(type_name));
+        if self.natives.contains_key(target) || constructs {
+            return self.call_native_or_variant(chunk, call, arguments, span);
+        }
+        let (function, function_name, receiver) = self.function_for(target, span)?;
+        self.check_arguments(call, &function, &arguments, span)?;
+        let body = Rc::clone(&function.chunk);
+        let result = self.invoke(&function, receiver, arguments, Some(span), |this| {
+            this.execute_chunk(&body)
+        })?;
+        let result = self.returned(&function, &function_name, result, span)?;
+        self.store_result(chunk, call, &function_name, result, span)
+    }
+
+    fn check_arguments(
+        &self,
+        call: &CallSite,
+        function: &Function,
+        arguments: &[Value],
+        span: Span,
+    ) -> Result<()> {
+        let parameters = &function.signature.parameters;
+        check_arity(&call.target, parameters.len(), arguments.len(), span)?;
+        for ((parameter, argument), &argument_span) in
+            parameters.iter().zip(arguments).zip(&call.arguments)
+        {
+            if let Some(annotation) = &parameter.annotation {
+                self.check_annotation(&parameter.name, Some(annotation), argument, argument_span)?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Stores what a function returned in the places after `into`, when the call has any
+    */
+    fn store_result(
+        &mut self,
+        chunk: &Chunk,
+        call: &CallSite,
+        function_name: &str,
+        result: Option<Value>,
+        span: Span,
+    ) -> Result<()> {
+        if call.into.is_empty() {
+            return Ok(());
+        }
+        let value = result.ok_or_else(|| {
+            NekoError::at(format!("{} does not return a value", function_name), span)
+        })?;
+        self.store_all(chunk, &call.into, value, call.into_span)
+    }
+
+    /*
+        Calls a native function or builds an enum value, neither of which runs any code of the program
+    */
+    fn call_native_or_variant(
+        &mut self,
+        chunk: &Chunk,
+        call: &CallSite,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<()> {
+        let target = call.target.as_str();
+        if let Some(native) = self.natives.get(target).cloned() {
+            let value = call_native(target, &native, &arguments, span)?;
+            if !call.into.is_empty() {
+                self.store_all(chunk, &call.into, value, call.into_span)?;
+            }
+            return Ok(());
+        }
+        if let Some((type_name, variant)) = target.split_once('.') {
+            if let Some(variants) = self.enum_types.get(type_name) {
+                let fields = variants
+                    .iter()
+                    .find(|candidate| candidate.name == variant)
+                    .map(|found| found.fields.len())
+                    .ok_or_else(|| NekoError::at(format!("Unknown variant: {}", target), span))?;
+                check_arity(target, fields, arguments.len(), span)?;
+                if call.into.is_empty() {
+                    return Err(NekoError::at(
+                        format!("Expected into after {}", target),
+                        span,
+                    ));
+                }
+                let value = Value::Enum(EnumValue {
+                    type_name: type_name.to_owned(),
+                    variant: variant.to_owned(),
+                    payload: arguments,
+                });
+                return self.store_all(chunk, &call.into, value, call.into_span);
+            }
+        }
+        unreachable!(
+            "{} is neither a native function nor an enum variant",
+            target
+        )
+    }
+}
+
+fn check_arity(target: &str, expected: usize, found: usize, span: Span) -> Result<()> {
+    if expected != found {
+        return Err(NekoError::at(
+            format!("{} expects {} arguments, found {}", target, expected, found),
+            span,
+        ));
+    }
+    Ok(())
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("stack underflow")
+}
+
+/*
+    The compiler only emits `sqrt` and `abs` on values it has checked to be integers
+*/
+fn integer(value: Value) -> i32 {
+    match value {
+        Value::Int(value) => value,
+        other => unreachable!("expected an integer on the stack, found {}", other),
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Lexeme {
     Var,
     Print,
@@ -25,11 +28,62 @@ pub enum Lexeme {
     ABS,
     POW,
     Switch,
+    Method,
+    Return,
+    Enum,
+    Match,
+    Tuple,
+    Concat,
+    Len,
+    Substr,
+    IndexOf,
+    Split,
+    Join,
+    Replace,
+    Trim,
+    Upper,
+    Lower,
+    StartsWith,
+    EndsWith,
+    Repeat,
+    Foreach,
+    Break,
+    Continue,
+    Map,
+    Sort,
+    SortDesc,
+    Reverse,
+    Contains,
+    BinarySearch,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    Slice,
+    Fill,
+    Const,
+    IsNone,
+    IsSome,
+    UnwrapOr,
+    Get,
+    Pop,
+    Set,
+    Input,
+    InputInt,
+    InputFloat,
 }
 
 impl Lexeme {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
-        match s {
+        Lexeme::parse(s).unwrap_or_else(|| panic!("Invalid lexeme: {}", s))
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        if element_type(s).is_some() {
+            return Some(Lexeme::Array);
+        }
+        let lexeme = match s {
             "var" => Lexeme::Var,
             "print" => Lexeme::Print,
             "if" => Lexeme::If,
@@ -56,11 +110,108 @@ impl Lexeme {
             "abs" => Lexeme::ABS,
             "pow" => Lexeme::POW,
             "switch" => Lexeme::Switch,
-            _ => panic!("Invalid lexeme: {}", s),
-        }
+            "method" => Lexeme::Method,
+            "return" => Lexeme::Return,
+            "enum" => Lexeme::Enum,
+            "match" => Lexeme::Match,
+            "tuple" => Lexeme::Tuple,
+            "concat" => Lexeme::Concat,
+            "len" => Lexeme::Len,
+            "substr" => Lexeme::Substr,
+            "index_of" => Lexeme::IndexOf,
+            "split" => Lexeme::Split,
+            "join" => Lexeme::Join,
+            "replace" => Lexeme::Replace,
+            "trim" => Lexeme::Trim,
+            "upper" => Lexeme::Upper,
+            "lower" => Lexeme::Lower,
+            "starts_with" => Lexeme::StartsWith,
+            "ends_with" => Lexeme::EndsWith,
+            "repeat" => Lexeme::Repeat,
+            "foreach" => Lexeme::Foreach,
+            "break" => Lexeme::Break,
+            "continue" => Lexeme::Continue,
+            "map" => Lexeme::Map,
+            "sort" => Lexeme::Sort,
+            "sort_desc" => Lexeme::SortDesc,
+            "reverse" => Lexeme::Reverse,
+            "contains" => Lexeme::Contains,
+            "binary_search" => Lexeme::BinarySearch,
+            "sum" => Lexeme::Sum,
+            "min" => Lexeme::Min,
+            "max" => Lexeme::Max,
+            "avg" => Lexeme::Avg,
+            "slice" => Lexeme::Slice,
+            "fill" => Lexeme::Fill,
+            "const" => Lexeme::Const,
+            "is_none" => Lexeme::IsNone,
+            "is_some" => Lexeme::IsSome,
+            "unwrap_or" => Lexeme::UnwrapOr,
+            "get" => Lexeme::Get,
+            "pop" => Lexeme::Pop,
+            "set" => Lexeme::Set,
+            "input" => Lexeme::Input,
+            "input_int" => Lexeme::InputInt,
+            "input_float" => Lexeme::InputFloat,
+            _ => return None,
+        };
+        Some(lexeme)
+    }
+
+    /*
+        Whether the statement stores its result in the name that follows the keyword,
+        so that name can not be a constant
+    */
+    pub fn assigns(self) -> bool {
+        matches!(
+            self,
+            Lexeme::Var
+                | Lexeme::Array
+                | Lexeme::String
+                | Lexeme::Float
+                | Lexeme::Struct
+                | Lexeme::Tuple
+                | Lexeme::Map
+                | Lexeme::Add
+                | Lexeme::Sub
+                | Lexeme::Mul
+                | Lexeme::Div
+                | Lexeme::AddF
+                | Lexeme::SubF
+                | Lexeme::MulF
+                | Lexeme::DivF
+                | Lexeme::Sqrt
+                | Lexeme::ABS
+                | Lexeme::POW
+                | Lexeme::Pop
+                | Lexeme::Set
+                | Lexeme::Input
+                | Lexeme::InputInt
+                | Lexeme::InputFloat
+        )
     }
 }
 
+/*
+    The element type of a typed array keyword such as `array<float>`
+*/
+pub fn element_type(word: &str) -> Option<&str> {
+    word.strip_prefix("array<")?.strip_suffix('>')
+}
+
+/*
+    Whether a word starts a statement or closes a block, which ends a list of operands or names
+    whose length is not known in advance, such as the values after `return`
+*/
+pub fn ends_list(word: &str) -> bool {
+    Lexeme::parse(word).is_some()
+        || matches!(
+            word,
+            "case" | "endswitch" | "endmatch" | "endstruct" | "endenum" | "endmap"
+        )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Comparison {
     Equal,
     NotEqual,
@@ -71,6 +222,7 @@ pub enum Comparison {
 }
 
 impl Comparison {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "==" => Comparison::Equal,
@@ -82,4 +234,360 @@ impl Comparison {
             _ => panic!("Invalid comparison operator: {}", s),
         }
     }
+
+    pub fn holds<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::LessThan => left < right,
+            Comparison::LessThanOrEqual => left <= right,
+            Comparison::GreaterThan => left > right,
+            Comparison::GreaterThanOrEqual => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::LessThan => "<",
+            Comparison::LessThanOrEqual => "<=",
+            Comparison::GreaterThan => ">",
+            Comparison::GreaterThanOrEqual => ">=",
+        };
+        write!(f, "{}", operator)
+    }
+}
+
+/*
+    Where a word starts in the source code, counting lines and columns from 1
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Word {
+    pub text: String,
+    pub span: Span,
+}
+
+/*
+    Splits source code into words separated by whitespace. A quoted string such as `"hello world"`
+    is kept as a single word, quotes included, so it can hold spaces.
+*/
+pub fn tokenize(source_code: &str) -> Vec<Word> {
+    let mut words = vec![];
+    let mut current: Option<Word> = None;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, line) in source_code.lines().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            let span = Span {
+                line: index + 1,
+                column: column + 1,
+            };
+            if quoted {
+                let word = current.as_mut().unwrap();
+                word.text.push(c);
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    quoted = false;
+                }
+            } else if c.is_whitespace() {
+                words.extend(current.take());
+            } else {
+                let word = current.get_or_insert_with(|| Word {
+                    text: String::new(),
+                    span,
+                });
+                word.text.push(c);
+                quoted = c == '"';
+            }
+        }
+        match current.as_mut() {
+            Some(word) if quoted => word.text.push('\n'),
+            _ => words.extend(current.take()),
+        }
+    }
+    words.extend(current);
+    words
+}
+
+/*
+    The integer `true` or `false` stands for, as bools are the integers 1 and 0
+*/
+pub fn boolean(word: &str) -> Option<i32> {
+    match word {
+        "true" => Some(1),
+        "false" => Some(0),
+        _ => None,
+    }
+}
+
+/*
+    The contents of a quoted word with its escapes resolved, or `None` if the word is not quoted
+*/
+pub fn unquote(word: &str) -> Option<String> {
+    let inner = word.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+    Some(text)
+}
+
+/*
+    An element of a tuple literal: the word of an operand, or a tuple nested in parentheses
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum TupleElement {
+    Word(String),
+    Tuple(Vec<TupleElement>),
+}
+
+/*
+    Reads the elements of a tuple literal such as `(1, "a, b", (2, 3))` up to its closing parenthesis.
+    Quoted strings are kept whole, as `tokenize` keeps them, and nested parentheses are read as tuples.
+*/
+fn parse_tuple(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Vec<TupleElement>> {
+    if chars.next()? != '(' {
+        return None;
+    }
+    let mut elements = vec![];
+    let mut word = String::new();
+    let mut nested = None;
+    loop {
+        match *chars.peek()? {
+            '(' if word.trim().is_empty() && nested.is_none() => nested = Some(parse_tuple(chars)?),
+            separator @ (',' | ')') => {
+                chars.next();
+                match nested.take() {
+                    Some(tuple) if word.trim().is_empty() => {
+                        elements.push(TupleElement::Tuple(tuple))
+                    }
+                    Some(_) => return None,
+                    None if !word.trim().is_empty() => {
+                        elements.push(TupleElement::Word(word.trim().to_owned()))
+                    }
+                    None => {}
+                }
+                word.clear();
+                if separator == ')' {
+                    return Some(elements);
+                }
+            }
+            '"' => {
+                word.push(chars.next()?);
+                let mut escaped = false;
+                loop {
+                    let c = chars.next()?;
+                    word.push(c);
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        break;
+                    }
+                }
+            }
+            c if nested.is_some() && !c.is_whitespace() => return None,
+            _ => word.push(chars.next()?),
+        }
+    }
+}
+
+/*
+    How many more parentheses a word opens than it closes, not counting those inside quotes
+*/
+fn nesting(word: &str) -> i32 {
+    let (mut depth, mut quoted, mut escaped) = (0, false, false);
+    for c in word.chars() {
+        if quoted {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                quoted = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => quoted = true,
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+/*
+    Cursor over the words of a program. Blocks are executed by cloning the cursor at their first word,
+    and skipped by scanning for the word that closes them.
+*/
+#[derive(Clone)]
+pub struct Tokens<'a> {
+    words: &'a [Word],
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(words: &'a [Word]) -> Self {
+        Tokens { words, position: 0 }
+    }
+
+    pub fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.position).map(|word| word.text.as_str())
+    }
+
+    /*
+        Reads the name a statement declares, together with its type when written as `name: type`
+    */
+    pub fn next_name(&mut self) -> Option<(&'a str, Option<&'a str>)> {
+        let word = self.next()?;
+        match word.strip_suffix(':') {
+            Some(name) => Some((name, self.next())),
+            None => Some((word, None)),
+        }
+    }
+
+    /*
+        Reads a tuple literal such as `(1, "a, b", (2, 3))`, which may span several words,
+        or nothing when the words up to its closing parenthesis do not form one
+    */
+    pub fn next_tuple(&mut self) -> Option<Vec<TupleElement>> {
+        let mut literal = String::new();
+        let mut depth = 0;
+        for word in self.by_ref() {
+            literal.push_str(word);
+            literal.push(' ');
+            depth += nesting(word);
+            if depth <= 0 {
+                break;
+            }
+        }
+        let mut chars = literal.trim().chars().peekable();
+        let elements = parse_tuple(&mut chars)?;
+        chars.next().is_none().then_some(elements)
+    }
+
+    /*
+        Reads words up to the next statement or the end of the enclosing block
+    */
+    pub fn next_list(&mut self) -> Vec<&'a str> {
+        let mut words = vec![];
+        while let Some(word) = self.peek() {
+            if ends_list(word) {
+                break;
+            }
+            words.push(word);
+            self.position += 1;
+        }
+        words
+    }
+
+    /*
+        Where the word read last starts
+    */
+    pub fn span(&self) -> Span {
+        match self.words.get(self.position.saturating_sub(1)) {
+            Some(word) => word.span,
+            None => Span { line: 1, column: 1 },
+        }
+    }
+
+    /*
+        Moves past a block up to, but not including, the first of `terminators` found outside any nested block,
+        returning the skipped words
+    */
+    pub fn skip_block(&mut self, terminators: &[&str]) -> &'a [Word] {
+        let start = self.position;
+        let mut closers: Vec<&str> = vec![];
+        while let Some(word) = self.peek() {
+            if closers.is_empty() && terminators.contains(&word) {
+                break;
+            }
+            self.position += 1;
+            if closers.last() == Some(&word) {
+                closers.pop();
+                continue;
+            }
+            if closers.last() == Some(&"endstring") {
+                continue;
+            }
+            closers.extend(closer(word));
+        }
+        &self.words[start..self.position]
+    }
+}
+
+/*
+    The word that closes a block opened by `word`
+*/
+fn closer(word: &str) -> Option<&'static str> {
+    match word {
+        "if" | "loop" | "foreach" | "function" | "method" => Some("end"),
+        "struct" => Some("endstruct"),
+        "string" => Some("endstring"),
+        "switch" => Some("endswitch"),
+        "enum" => Some("endenum"),
+        "match" => Some("endmatch"),
+        "map" => Some("endmap"),
+        _ => None,
+    }
+}
+
+/*
+    Whether every block and quoted string opened in `words` is closed again, so they can be run
+    as they are. Closers without a block to close are left for the interpreter to report.
+*/
+pub fn is_complete(words: &[Word]) -> bool {
+    let mut closers: Vec<&str> = vec![];
+    for word in words.iter().map(|word| word.text.as_str()) {
+        if closers.last() == Some(&word) {
+            closers.pop();
+        } else if closers.last() != Some(&"endstring") {
+            closers.extend(closer(word));
+        }
+    }
+    let open_quote = words
+        .last()
+        .is_some_and(|word| word.text.starts_with('"') && unquote(&word.text).is_none());
+    closers.is_empty() && !open_quote
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.peek()?;
+        self.position += 1;
+        Some(word)
+    }
 }
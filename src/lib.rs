@@ -1,2 +1,13 @@
+pub mod builtins;
+pub mod bytecode;
+pub mod checker;
+pub mod compiler;
+pub mod error;
+pub mod format;
 pub mod interpreter;
-pub mod lexeme;
\ No newline at end of file
+pub mod lexeme;
+pub mod native;
+pub mod optimizer;
+pub mod repl;
+pub mod types;
+pub mod value;
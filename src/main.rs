@@ -1,6 +1,9 @@
 use std::{env, fs};
 
-use my_project::interpreter::Interpreter;
+use my_project::checker;
+use my_project::interpreter::{Interpreter, StdinLines};
+use my_project::optimizer::Optimization;
+use my_project::repl::Repl;
 
 static ERROR_MISSING_INPUT_FILE: &str = "Missing input file";
 static ERROR_FILE_READ: &str = "Could not read file";
@@ -9,7 +12,20 @@ static ERROR_BAD_EXTENSION: &str = "Source files must have the .nek file extensi
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
 
-    let source_file = args.iter().skip(1).next().ok_or(ERROR_MISSING_INPUT_FILE)?;
+    // `--opt` optimises the program before it runs, and `--dump` prints the compiled program instead of running it
+    let optimize = args.iter().any(|arg| arg == "--opt");
+    let dump = args.iter().any(|arg| arg == "--dump");
+    let args: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    // Without arguments `neko` starts an interactive session
+    if args.len() < 2 {
+        Repl::new(Interpreter::new(), true).run(StdinLines::default())?;
+        return Ok(());
+    }
+
+    // `neko check file.nek` only checks the program, without running it
+    let check_only = args.get(1).is_some_and(|arg| *arg == "check");
+    let source_file = args.get(if check_only { 2 } else { 1 }).ok_or(ERROR_MISSING_INPUT_FILE)?;
     
     if ! source_file.ends_with(".nek"){
         return Err(ERROR_BAD_EXTENSION.into());
@@ -17,8 +33,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let code = fs::read_to_string(source_file).map_err(|e| format!("{} {}: {}", ERROR_FILE_READ, source_file, e))?;
 
+    if check_only {
+        checker::check(&code).map_err(|e| format!("{}: {}", source_file, e))?;
+        println!("{}: no problems found", source_file);
+        return Ok(());
+    }
+
     let mut interpreter = Interpreter::new();
-    interpreter.run(code.as_str())?;
+    if optimize {
+        interpreter.set_optimization(Optimization::Full);
+    }
+
+    if dump {
+        let chunk = interpreter.compile(&code).map_err(|e| e.to_string())?;
+        print!("{}", chunk);
+        return Ok(());
+    }
+
+    interpreter
+        .run(code.as_str())
+        .map_err(|e| e.to_string())?;
 
     Ok(())
 }
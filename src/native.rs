@@ -0,0 +1,179 @@
+use crate::error::{NekoError, Result};
+use crate::value::Value;
+use std::rc::Rc;
+
+/*
+    A function written in Rust that programs call like one of their own, as in `call http_status code into status`.
+    It is given the values of the arguments and its result is bound to the names after `into`.
+*/
+pub type NativeFunction = Rc<dyn Fn(&[Value]) -> Result<Value>>;
+
+/*
+    A Rust value a Neko value can be converted to, failing when the Neko value is of another type
+*/
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Int(value) => Ok(value),
+            other => Err(expected("an integer", &other)),
+        }
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Float(value) => Ok(value),
+            other => Err(expected("a float", &other)),
+        }
+    }
+}
+
+/*
+    Booleans are the integers 1 and 0, as `true` and `false` are
+*/
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Int(1) => Ok(true),
+            Value::Int(0) => Ok(false),
+            other => Err(expected("true or false", &other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Str(text) => Ok(text),
+            other => Err(expected("a string", &other)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Array(values) => values.into_iter().map(T::from_value).collect(),
+            other => Err(expected("an array", &other)),
+        }
+    }
+}
+
+/*
+    `none` converts to `None`, and any other value to what it holds
+*/
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::None => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Int(value as i32)
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Str(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Str(text.to_owned())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::None, Into::into)
+    }
+}
+
+/*
+    The arguments a native function expects, as a tuple of Rust values
+*/
+pub trait FromArguments: Sized {
+    fn from_arguments(arguments: &[Value]) -> Result<Self>;
+}
+
+impl FromArguments for () {
+    fn from_arguments(arguments: &[Value]) -> Result<Self> {
+        check_arity(0, arguments)
+    }
+}
+
+macro_rules! tuple_arguments {
+    ($count:literal: $($name:ident)+) => {
+        impl<$($name: FromValue),+> FromArguments for ($($name,)+) {
+            fn from_arguments(arguments: &[Value]) -> Result<Self> {
+                check_arity($count, arguments)?;
+                let mut arguments = arguments.iter().cloned();
+                Ok(($($name::from_value(arguments.next().unwrap())?,)+))
+            }
+        }
+    };
+}
+
+tuple_arguments!(1: A);
+tuple_arguments!(2: A B);
+tuple_arguments!(3: A B C);
+tuple_arguments!(4: A B C D);
+tuple_arguments!(5: A B C D E);
+
+/*
+    Checks a native function was called with as many arguments as it expects and converts them,
+    e.g. `let (code, verbose): (i32, bool) = native::arguments(args)?;`
+*/
+pub fn arguments<T: FromArguments>(arguments: &[Value]) -> Result<T> {
+    T::from_arguments(arguments)
+}
+
+fn check_arity(expected: usize, arguments: &[Value]) -> Result<()> {
+    if arguments.len() != expected {
+        return Err(NekoError::new(format!(
+            "Expected {} arguments, found {}",
+            expected,
+            arguments.len()
+        )));
+    }
+    Ok(())
+}
+
+fn expected(kind: &str, found: &Value) -> NekoError {
+    NekoError::new(format!("Expected {}, found {}", kind, found))
+}
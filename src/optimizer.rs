@@ -0,0 +1,470 @@
+use crate::bytecode::{Arithmetic, Chunk, Op, Operand, Place};
+use crate::format;
+use crate::interpreter::compare;
+use crate::value::Value;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/*
+    How much a compiled program is changed before it runs
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Optimization {
+    #[default]
+    Off,
+    /*
+        Folds constants and removes code that can never run. Stores to the variables of the program
+        are kept, since a REPL or the host can read them once the program has run.
+    */
+    Safe,
+    /*
+        Also drops stores to variables of the program that nothing reads, for a program that is run once
+    */
+    Full,
+}
+
+/*
+    Optimises a chunk and the functions it defines. Within a block of straight-line code, numbers stored
+    from constants, and those given to `const`, are carried into the instructions that read them, arithmetic on known numbers becomes
+    a store of the result, and an `if` comparing known numbers loses the branch that can not run.
+    Code no jump or fall-through reaches is then removed, along with stores to function locals that
+    nothing reads. The optimised chunk prints and fails exactly as the original does.
+*/
+pub fn optimize(mut chunk: Chunk, optimization: Optimization) -> Chunk {
+    if optimization == Optimization::Off {
+        return chunk;
+    }
+    optimize_code(&mut chunk, optimization == Optimization::Full);
+    chunk
+}
+
+/*
+    Runs the passes until they find nothing more to do, since each can open the way for the others:
+    a removed branch joins blocks of straight-line code, and removed code reads fewer variables
+*/
+fn optimize_code(chunk: &mut Chunk, drop_dead_stores: bool) {
+    loop {
+        let before = chunk.code.clone();
+        propagate(chunk);
+        if drop_dead_stores {
+            drop_unread_stores(chunk);
+        }
+        fold(chunk);
+        drop_unreachable(chunk);
+        drop_jumps_to_next(chunk);
+        if chunk.code == before {
+            break;
+        }
+    }
+    for (_, function) in &mut chunk.functions {
+        let mut body = function.chunk.as_ref().clone();
+        optimize_code(&mut body, true);
+        function.chunk = Rc::new(body);
+    }
+}
+
+/*
+    Replaces reads of slots and constants known to hold a number with the number, and arithmetic on
+    known numbers with a store of its result. What is known is forgotten wherever a jump can land,
+    except for constants defined before the first place one can, which every run defines.
+*/
+fn propagate(chunk: &mut Chunk) {
+    let leaders = leaders(chunk);
+    let entry = entry_end(chunk);
+    let mut known: HashMap<usize, Value> = HashMap::new();
+    let mut constants: HashMap<usize, (Value, bool)> = HashMap::new();
+    let mut pushed = None;
+    let mut replacements = vec![None; chunk.code.len()];
+    for index in 0..chunk.code.len() {
+        if leaders[index] {
+            known.clear();
+            constants.retain(|_, (_, always)| *always);
+            pushed = None;
+        }
+        let mut op = chunk.code[index].clone();
+        match &mut op {
+            Op::Push(operand) | Op::Int(operand) | Op::Float(operand) | Op::Number(operand) => {
+                substitute(chunk, &known, &constants, operand)
+            }
+            Op::Arithmetic(site) => {
+                let mut operand = chunk.arithmetic[*site].operand;
+                substitute(chunk, &known, &constants, &mut operand);
+                chunk.arithmetic[*site].operand = operand;
+                let site = chunk.arithmetic[*site].clone();
+                if let Place::Slot(slot) = site.target {
+                    let right = match operand {
+                        Operand::Constant(index) => Some(&chunk.constants[index]),
+                        _ => None,
+                    };
+                    let result = match (known.get(&slot), right) {
+                        (Some(left), Some(right)) => arithmetic(site.operation, left, right),
+                        _ => None,
+                    };
+                    known.remove(&slot);
+                    if let Some(result) = result {
+                        let constant = constant(chunk, result.clone());
+                        known.insert(slot, result);
+                        replacements[index] = Some(vec![
+                            Op::Push(Operand::Constant(constant)),
+                            Op::Store(site.target),
+                        ]);
+                        pushed = None;
+                        continue;
+                    }
+                }
+            }
+            Op::Store(Place::Slot(slot)) | Op::StoreLocal(Place::Slot(slot)) => {
+                match pushed.take() {
+                    Some(value @ (Value::Int(_) | Value::Float(_))) => known.insert(*slot, value),
+                    _ => known.remove(slot),
+                };
+            }
+            Op::DefineConstant(name) => {
+                if let Some(value @ (Value::Int(_) | Value::Float(_))) = pushed.take() {
+                    constants.insert(*name, (value, index < entry));
+                }
+            }
+            Op::Call(_) => known.clear(),
+            Op::Next(iteration) => {
+                let iteration = &chunk.iterations[*iteration];
+                for place in iteration.key.iter().chain([&iteration.item]) {
+                    if let Place::Slot(slot) = place {
+                        known.remove(slot);
+                    }
+                }
+            }
+            _ => {}
+        }
+        pushed = match op {
+            Op::Push(Operand::Constant(index)) => Some(chunk.constants[index].clone()),
+            _ => None,
+        };
+        replacements[index] = Some(vec![op]);
+    }
+    rebuild(chunk, replacements);
+}
+
+fn substitute(
+    chunk: &mut Chunk,
+    known: &HashMap<usize, Value>,
+    constants: &HashMap<usize, (Value, bool)>,
+    operand: &mut Operand,
+) {
+    let value = match operand {
+        Operand::Slot(slot) => known.get(slot),
+        Operand::Name(name) => constants.get(name).map(|(value, _)| value),
+        Operand::Constant(_) => None,
+    };
+    if let Some(value) = value {
+        *operand = Operand::Constant(constant(chunk, value.clone()));
+    }
+}
+
+/*
+    The result of arithmetic on two known numbers, unless working it out would fail or overflow,
+    which is left for the program to do when it runs
+*/
+fn arithmetic(operation: Arithmetic, left: &Value, right: &Value) -> Option<Value> {
+    match (left, right) {
+        (Value::Float(left), Value::Float(right)) if operation.on_floats() => {
+            Some(Value::Float(operation.floats(*left, *right)))
+        }
+        (Value::Int(left), Value::Int(right)) if !operation.on_floats() => {
+            operation.integers(*left, *right).ok().map(Value::Int)
+        }
+        _ => None,
+    }
+}
+
+/*
+    Works out comparisons of two constants and `sqrt` and `abs` of one, and drops constants pushed only to be popped
+*/
+fn fold(chunk: &mut Chunk) {
+    let leaders = leaders(chunk);
+    let code = &chunk.code;
+    let constant_at = |index: usize| match code.get(index) {
+        Some(Op::Number(Operand::Constant(constant)) | Op::Int(Operand::Constant(constant))) => {
+            match &chunk.constants[*constant] {
+                value @ (Value::Int(_) | Value::Float(_)) => Some(value),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    let mut replacements = vec![None; code.len()];
+    let mut results = vec![];
+    let mut index = 0;
+    while index < code.len() {
+        let follows = |offset: usize| !leaders[index + offset];
+        match (&code[index], code.get(index + 1), code.get(index + 2)) {
+            (Op::Number(_), Some(Op::Number(_)), Some(Op::JumpUnless { comparison, target }))
+                if follows(1) && follows(2) =>
+            {
+                if let (Some(bound), Some(left)) = (constant_at(index), constant_at(index + 1)) {
+                    let jump = match compare(comparison, left, bound) {
+                        true => vec![],
+                        false => vec![Op::Jump(*target)],
+                    };
+                    replacements[index] = Some(jump);
+                    replacements[index + 1] = Some(vec![]);
+                    replacements[index + 2] = Some(vec![]);
+                    index += 3;
+                    continue;
+                }
+            }
+            (Op::Int(_), Some(op @ (Op::SqrtInt | Op::AbsInt)), _) if follows(1) => {
+                let result = match (constant_at(index), op) {
+                    (Some(Value::Int(value)), Op::SqrtInt) => Some((*value as f32).sqrt() as i32),
+                    // abs of the smallest int overflows, which is left for the program to report
+                    (Some(Value::Int(value)), _) => value.checked_abs(),
+                    _ => None,
+                };
+                if let Some(result) = result {
+                    results.push((index, Value::Int(result)));
+                    replacements[index + 1] = Some(vec![]);
+                    index += 2;
+                    continue;
+                }
+            }
+            (Op::Push(Operand::Constant(_)), Some(Op::Pop), _) if follows(1) => {
+                replacements[index] = Some(vec![]);
+                replacements[index + 1] = Some(vec![]);
+                index += 2;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    for (index, result) in results {
+        let result = constant(chunk, result);
+        replacements[index] = Some(vec![Op::Push(Operand::Constant(result))]);
+    }
+    rebuild(chunk, replacements);
+}
+
+/*
+    Replaces stores to slots that nothing reads with pops, leaving `self` alone since a method
+    hands it back to its caller
+*/
+fn drop_unread_stores(chunk: &mut Chunk) {
+    let read = read_slots(chunk);
+    let mut replacements = vec![None; chunk.code.len()];
+    for (index, op) in chunk.code.iter().enumerate() {
+        if let Op::Store(Place::Slot(slot)) | Op::StoreLocal(Place::Slot(slot)) = op {
+            if !read.contains(slot) && chunk.slots.name(*slot) != "self" {
+                replacements[index] = Some(vec![Op::Pop]);
+            }
+        }
+    }
+    rebuild(chunk, replacements);
+}
+
+/*
+    The slots any instruction reads, directly or through a name looked up while the chunk runs
+*/
+fn read_slots(chunk: &Chunk) -> HashSet<usize> {
+    let mut read = HashSet::new();
+    let mut names: Vec<&str> = vec![];
+    let mut templates = vec![];
+    for op in &chunk.code {
+        let operands = match op {
+            Op::Push(operand)
+            | Op::Int(operand)
+            | Op::Float(operand)
+            | Op::Number(operand)
+            | Op::Iterate(operand)
+            | Op::LoopUnless { operand, .. } => vec![*operand],
+            Op::Arithmetic(site) => {
+                let site = &chunk.arithmetic[*site];
+                vec![site.target.into(), site.operand]
+            }
+            Op::Store(Place::Name(name)) | Op::StoreLocal(Place::Name(name)) => {
+                vec![Operand::Name(*name)]
+            }
+            Op::Print(name) | Op::PopArray(name) => vec![Operand::Name(*name)],
+            Op::PrintFormat(template) => {
+                if let Value::Str(template) = &chunk.constants[*template] {
+                    templates.extend(format::names(template).unwrap_or_default());
+                }
+                vec![]
+            }
+            Op::Match(table) => {
+                names.push(&chunk.matches[*table].name);
+                vec![]
+            }
+            Op::Call(call) => {
+                names.push(&chunk.calls[*call].target);
+                vec![]
+            }
+            Op::MakeStruct(layout) => {
+                names.extend(chunk.layouts[*layout].prototype.as_deref());
+                vec![]
+            }
+            _ => vec![],
+        };
+        for operand in operands {
+            match operand {
+                Operand::Slot(slot) => {
+                    read.insert(slot);
+                }
+                Operand::Name(name) => names.push(&chunk.names[name]),
+                Operand::Constant(_) => {}
+            }
+        }
+    }
+    let roots = names
+        .into_iter()
+        .chain(templates.iter().map(String::as_str))
+        .map(|name| name.split('.').next().unwrap_or(name));
+    read.extend(roots.filter_map(|root| chunk.slots.get(root)));
+    read
+}
+
+/*
+    Removes instructions that no path from the start of the chunk reaches
+*/
+fn drop_unreachable(chunk: &mut Chunk) {
+    let mut reached = vec![false; chunk.code.len()];
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+        if index >= chunk.code.len() || reached[index] {
+            continue;
+        }
+        reached[index] = true;
+        pending.extend(successors(chunk, index));
+    }
+    let replacements = reached
+        .into_iter()
+        .map(|reached| if reached { None } else { Some(vec![]) })
+        .collect();
+    rebuild(chunk, replacements);
+}
+
+fn drop_jumps_to_next(chunk: &mut Chunk) {
+    let replacements = chunk
+        .code
+        .iter()
+        .enumerate()
+        .map(|(index, op)| match op {
+            Op::Jump(target) if *target == index + 1 => Some(vec![]),
+            _ => None,
+        })
+        .collect();
+    rebuild(chunk, replacements);
+}
+
+/*
+    Where the program can go after the instruction at `index`
+*/
+fn successors(chunk: &Chunk, index: usize) -> Vec<usize> {
+    match &chunk.code[index] {
+        Op::Jump(target) => vec![*target],
+        Op::JumpUnless { target, .. } | Op::LoopUnless { target, .. } => vec![index + 1, *target],
+        Op::Next(iteration) => vec![index + 1, chunk.iterations[*iteration].exit],
+        Op::Switch(table) => {
+            let table = &chunk.switches[*table];
+            let cases = table.cases.iter().map(|(_, target)| *target);
+            cases.chain([table.end]).collect()
+        }
+        Op::Match(table) => chunk.matches[*table]
+            .cases
+            .iter()
+            .map(|case| case.target)
+            .collect(),
+        Op::Return(_) | Op::Break | Op::Continue | Op::OutsideLoop => vec![],
+        _ => vec![index + 1],
+    }
+}
+
+/*
+    Marks the instructions a jump can land on, and those following a jump or a branch
+*/
+fn leaders(chunk: &Chunk) -> Vec<bool> {
+    let mut leaders = vec![false; chunk.code.len() + 1];
+    leaders[0] = true;
+    for (index, op) in chunk.code.iter().enumerate() {
+        if !matches!(successors(chunk, index)[..], [next] if next == index + 1)
+            || matches!(op, Op::Call(_))
+        {
+            leaders[index + 1] = true;
+            for target in successors(chunk, index) {
+                leaders[target] = true;
+            }
+        }
+    }
+    leaders
+}
+
+/*
+    Where the straight-line code every run of the chunk starts with ends: at the first instruction
+    a jump can land on, or that follows a branch
+*/
+fn entry_end(chunk: &Chunk) -> usize {
+    let mut end = chunk.code.len();
+    for index in 0..chunk.code.len() {
+        let successors = successors(chunk, index);
+        if successors != [index + 1] {
+            end = end.min(index + 1);
+        }
+        for target in successors {
+            if target != index + 1 {
+                end = end.min(target);
+            }
+        }
+    }
+    end
+}
+
+/*
+    Puts `replacements[index]` in place of the instruction at `index`, keeping the instruction where it is `None`,
+    and points every jump at where its target ended up. A jump to a removed instruction lands on whatever follows it.
+*/
+fn rebuild(chunk: &mut Chunk, mut replacements: Vec<Option<Vec<Op>>>) {
+    let mut code = Vec::with_capacity(chunk.code.len());
+    let mut spans = Vec::with_capacity(chunk.code.len());
+    let mut moved = Vec::with_capacity(chunk.code.len() + 1);
+    for (index, op) in chunk.code.drain(..).enumerate() {
+        moved.push(code.len());
+        let ops = replacements[index].take().unwrap_or_else(|| vec![op]);
+        spans.extend(ops.iter().map(|_| chunk.spans[index]));
+        code.extend(ops);
+    }
+    moved.push(code.len());
+    for op in &mut code {
+        if let Op::Jump(target) | Op::JumpUnless { target, .. } | Op::LoopUnless { target, .. } = op
+        {
+            *target = moved[*target];
+        }
+    }
+    for iteration in &mut chunk.iterations {
+        iteration.exit = moved[iteration.exit];
+    }
+    for table in &mut chunk.switches {
+        for (_, target) in &mut table.cases {
+            *target = moved[*target];
+        }
+        table.end = moved[table.end];
+    }
+    for table in &mut chunk.matches {
+        for case in &mut table.cases {
+            case.target = moved[case.target];
+        }
+    }
+    chunk.code = code;
+    chunk.spans = spans;
+}
+
+fn constant(chunk: &mut Chunk, value: Value) -> usize {
+    match chunk
+        .constants
+        .iter()
+        .position(|constant| *constant == value)
+    {
+        Some(index) => index,
+        None => {
+            chunk.constants.push(value);
+            chunk.constants.len() - 1
+        }
+    }
+}
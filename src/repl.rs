@@ -0,0 +1,141 @@
+use crate::error::{NekoError, Result};
+use crate::interpreter::{Interpreter, StdinLines};
+use crate::lexeme::{self, Lexeme};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "neko> ";
+const CONTINUATION: &str = "....> ";
+
+const HELP: &str = "\
+:vars         list variables and constants
+:funcs        list functions and methods
+:reset        forget everything defined so far
+:load FILE    run a .nek file in this session
+:quit         leave";
+
+/*
+    An interactive session that keeps one interpreter alive across entries. Lines are collected until
+    every block they open is closed, then run together. An entry that does not start with a keyword is
+    an expression whose value is printed, and errors are printed without ending the session.
+*/
+pub struct Repl<T: Write, R: BufRead = StdinLines> {
+    interpreter: Interpreter<T, R>,
+    prompts: bool,
+}
+
+impl<T: Write, R: BufRead> Repl<T, R> {
+    /*
+        Prompts are written to the interpreter's output stream, and can be left out
+        when the input does not come from a person
+    */
+    pub fn new(interpreter: Interpreter<T, R>, prompts: bool) -> Self {
+        Repl {
+            interpreter,
+            prompts,
+        }
+    }
+
+    pub fn into_interpreter(self) -> Interpreter<T, R> {
+        self.interpreter
+    }
+
+    /*
+        Reads entries until the input ends or `:quit` is entered
+    */
+    pub fn run(&mut self, input: impl BufRead) -> io::Result<()> {
+        let mut entry = String::new();
+        self.prompt(PROMPT)?;
+        for line in input.lines() {
+            entry.push_str(&line?);
+            entry.push('\n');
+            if !lexeme::is_complete(&lexeme::tokenize(&entry)) {
+                self.prompt(CONTINUATION)?;
+                continue;
+            }
+            let entry = std::mem::take(&mut entry);
+            if entry.trim() == ":quit" {
+                return Ok(());
+            }
+            if let Err(error) = self.execute(entry.trim()) {
+                writeln!(self.interpreter.output_stream(), "Error: {}", error)?;
+            }
+            self.prompt(PROMPT)?;
+        }
+        Ok(())
+    }
+
+    /*
+        Runs one complete entry: a meta-command, an expression or statements
+    */
+    pub fn execute(&mut self, entry: &str) -> Result<()> {
+        if let Some(command) = entry.strip_prefix(':') {
+            return self.meta_command(command);
+        }
+        let words = lexeme::tokenize(entry);
+        let Some(first) = words.first() else {
+            return Ok(());
+        };
+        if Lexeme::parse(&first.text).is_some() {
+            self.interpreter.run(entry)?;
+        } else if words.len() == 1 && self.interpreter.get(&first.text).is_some() {
+            self.interpreter.run(&format!("print {}", first.text))?;
+        } else {
+            let value = self.interpreter.evaluate(entry)?;
+            writeln!(self.interpreter.output_stream(), "{}", value)?;
+        }
+        Ok(())
+    }
+
+    fn meta_command(&mut self, command: &str) -> Result<()> {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+        match name {
+            "vars" => {
+                let constants = self.interpreter.constants();
+                let bindings = self.interpreter.bindings();
+                let output = self.interpreter.output_stream();
+                for (name, value) in constants {
+                    writeln!(output, "const {} = {}", name, value)?;
+                }
+                for (name, value) in bindings {
+                    writeln!(output, "{} = {}", name, value)?;
+                }
+            }
+            "funcs" => {
+                let functions = self.interpreter.functions();
+                let output = self.interpreter.output_stream();
+                for (name, signature) in functions {
+                    writeln!(output, "{} {}", name, signature)?;
+                }
+            }
+            "reset" => self.interpreter.reset(),
+            "load" if !argument.is_empty() => {
+                let code = fs::read_to_string(argument).map_err(|error| {
+                    NekoError::new(format!("Could not read file {}: {}", argument, error))
+                })?;
+                self.interpreter.run(&code)?;
+            }
+            "load" => return Err(NekoError::new("Expected a file after :load")),
+            "help" => writeln!(self.interpreter.output_stream(), "{}", HELP)?,
+            _ => {
+                return Err(NekoError::new(format!(
+                    "Unknown command: :{} (:help lists the commands)",
+                    name
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn prompt(&mut self, prompt: &str) -> io::Result<()> {
+        if self.prompts {
+            let output = self.interpreter.output_stream();
+            write!(output, "{}", prompt)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
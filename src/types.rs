@@ -0,0 +1,196 @@
+use crate::lexeme::Tokens;
+use crate::value::Value;
+use std::fmt;
+
+/*
+    What is known about a value before the program runs. `Unknown` stands for values that can not be
+    followed statically, such as the result of a function without a return annotation, and fits anywhere.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+    Array,
+    Tuple(Vec<Type>),
+    Map,
+    Struct {
+        type_name: String,
+        fields: Vec<(String, Type)>,
+    },
+    Enum(String),
+    None,
+    Unknown,
+}
+
+impl Type {
+    pub fn of(value: &Value) -> Type {
+        match value {
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Str(_) => Type::Str,
+            Value::Array(_) => Type::Array,
+            Value::Tuple(values) => Type::Tuple(values.iter().map(Type::of).collect()),
+            Value::Map(_) => Type::Map,
+            Value::Struct(instance) => Type::Struct {
+                type_name: instance.type_name.clone(),
+                fields: instance
+                    .fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Type::of(value)))
+                    .collect(),
+            },
+            Value::Enum(value) => Type::Enum(value.type_name.clone()),
+            Value::None => Type::None,
+        }
+    }
+
+    /*
+        The name an annotation uses for this type: a builtin type name, or the name of a struct or enum type
+    */
+    pub fn name(&self) -> &str {
+        match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Str => "string",
+            Type::Array => "array",
+            Type::Tuple(_) => "tuple",
+            Type::Map => "map",
+            Type::Struct { type_name, .. } => type_name,
+            Type::Enum(type_name) => type_name,
+            Type::None => "none",
+            Type::Unknown => "unknown",
+        }
+    }
+
+    /*
+        The type of a struct field, tuple element or map entry
+    */
+    pub fn field(&self, name: &str) -> Option<Type> {
+        match self {
+            Type::Struct { fields, .. } => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, field)| field.clone()),
+            Type::Tuple(types) => types.get(name.parse::<usize>().ok()?).cloned(),
+            Type::Map | Type::Unknown => Some(Type::Unknown),
+            _ => None,
+        }
+    }
+
+    /*
+        Whether a value of this type can be an element of an `array<element_type>`,
+        where integers are taken as floats
+    */
+    pub fn fits_element(&self, element_type: &str) -> bool {
+        self.fits(element_type) || (*self == Type::Int && element_type == "float")
+    }
+
+    /*
+        Whether a value of this type can be stored under `annotation`. An optional type such as `int?`
+        also holds `none`.
+    */
+    pub fn fits(&self, annotation: &str) -> bool {
+        if let Some(annotation) = annotation.strip_suffix('?') {
+            return *self == Type::None || self.fits(annotation);
+        }
+        *self == Type::Unknown
+            || self.name() == annotation
+            || (*self == Type::Int && annotation == "bool")
+    }
+}
+
+/*
+    The type names that can be used in annotations besides the names of struct and enum types
+*/
+pub const BUILTIN_TYPES: [&str; 7] = ["int", "bool", "float", "string", "array", "tuple", "map"];
+
+/*
+    The type an annotation stands for, as far as it is known without a declaration to look at
+*/
+pub fn from_annotation(annotation: &str) -> Type {
+    match annotation {
+        "int" | "bool" => Type::Int,
+        "float" => Type::Float,
+        "string" => Type::Str,
+        "array" => Type::Array,
+        "map" => Type::Map,
+        _ => Type::Unknown,
+    }
+}
+
+/*
+    A value as an element of an `array<element_type>`: integers become floats in a float array
+    and a bool must be 0 or 1. `None` when the value does not fit.
+*/
+pub fn element(value: Value, element_type: &str) -> Option<Value> {
+    match (&value, element_type) {
+        (Value::Int(number), "float") => Some(Value::Float(*number as f32)),
+        (Value::Int(number), "bool") if *number != 0 && *number != 1 => None,
+        _ if Type::of(&value).fits(element_type) => Some(value),
+        _ => None,
+    }
+}
+
+/*
+    A parameter of a function or method, with the type it was annotated with if any
+*/
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub annotation: Option<String>,
+}
+
+/*
+    The parameters and optional return type of a function, e.g. `a: int b: float -> float`
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Signature {
+    pub parameters: Vec<Parameter>,
+    pub returns: Option<String>,
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", parameter.name)?;
+            if let Some(annotation) = &parameter.annotation {
+                write!(f, ": {}", annotation)?;
+            }
+        }
+        if let Some(returns) = &self.returns {
+            if !self.parameters.is_empty() {
+                write!(f, " ")?;
+            }
+            write!(f, "-> {}", returns)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+    Reads a function signature up to and including `with`
+*/
+pub fn read_signature(source: &mut Tokens) -> Signature {
+    let mut signature = Signature::default();
+    while let Some(word) = source.peek() {
+        if word == "with" {
+            source.next();
+            break;
+        }
+        if word == "->" {
+            source.next();
+            signature.returns = source.next().map(str::to_owned);
+            continue;
+        }
+        let (name, annotation) = source.next_name().unwrap();
+        signature.parameters.push(Parameter {
+            name: name.to_owned(),
+            annotation: annotation.map(str::to_owned),
+        });
+    }
+    signature
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::io::{Result, Write};
+
+/*
+    Values are copied whenever they are bound: by `set`, `into`, struct fields, array elements,
+    function arguments and return values alike, so two names never share an array, struct or map.
+    The one exception is the receiver of a method, which sees the changes the method makes to `self`.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Array(Vec<Value>),
+    Struct(Struct),
+    Enum(EnumValue),
+    Tuple(Vec<Value>),
+    Map(Fields),
+    None,
+}
+
+/*
+    Struct fields or map entries in declaration order, so they always print the same way
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fields {
+    entries: Vec<(String, Value)>,
+}
+
+impl Fields {
+    pub fn new() -> Self {
+        Fields {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.entries
+            .iter_mut()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /*
+        Redeclaring a field replaces its value but keeps its original position
+    */
+    pub fn insert(&mut self, name: String, value: Value) {
+        match self.get_mut(&name) {
+            Some(existing) => *existing = value,
+            None => self.entries.push((name, value)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    fn size(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|(key, value)| key.len() + value.size())
+            .sum()
+    }
+}
+
+/*
+    A struct instance. The type name decides which methods can be called on it
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Struct {
+    pub type_name: String,
+    pub fields: Fields,
+}
+
+/*
+    A value of an enum type: the variant it was built with and the payload given to it
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnumValue {
+    pub type_name: String,
+    pub variant: String,
+    pub payload: Vec<Value>,
+}
+
+impl Value {
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Struct(instance) => instance.fields.get(name),
+            Value::Map(entries) => entries.get(name),
+            Value::Tuple(values) => values.get(name.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut Value> {
+        match self {
+            Value::Struct(instance) => instance.fields.get_mut(name),
+            Value::Map(entries) => entries.get_mut(name),
+            Value::Tuple(values) => values.get_mut(name.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+
+    /*
+        Roughly how many bytes a value takes, counting the text of its strings and names
+        and everything its elements and fields hold, as memory limits count it
+    */
+    pub fn size(&self) -> usize {
+        let held = match self {
+            Value::Str(text) => text.len(),
+            Value::Array(values) | Value::Tuple(values) => values.iter().map(Value::size).sum(),
+            Value::Struct(instance) => instance.type_name.len() + instance.fields.size(),
+            Value::Map(entries) => entries.size(),
+            Value::Enum(value) => {
+                let payload: usize = value.payload.iter().map(Value::size).sum();
+                value.type_name.len() + value.variant.len() + payload
+            }
+            Value::Int(_) | Value::Float(_) | Value::None => 0,
+        };
+        std::mem::size_of::<Value>() + held
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{}", value),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Value::Struct(instance) => {
+                let fields: Vec<String> = instance
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect();
+                write!(f, "{} {{{}}}", instance.type_name, fields.join(", "))
+            }
+            Value::None => write!(f, "none"),
+            Value::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Value::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Value::Enum(value) => {
+                write!(f, "{}.{}", value.type_name, value.variant)?;
+                if !value.payload.is_empty() {
+                    let payload: Vec<String> = value
+                        .payload
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect();
+                    write!(f, "({})", payload.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/*
+    Writes a value the way `print` shows it when it is reached through `path`.
+    Scalars are written on their own, arrays one element per line and structs one field per line,
+    with the fields of nested structs indented below a `path:` header.
+*/
+pub fn write_value<T: Write>(output_stream: &mut T, path: &str, value: &Value) -> Result<()> {
+    match value {
+        Value::Struct(instance) => write_fields(output_stream, path, &instance.fields, 0),
+        Value::Array(values) => write_elements(output_stream, path, values, 0),
+        Value::Map(entries) => write_entries(output_stream, path, entries, 0),
+        _ => writeln!(output_stream, "{}", value),
+    }
+}
+
+pub fn write_fields<T: Write>(
+    output_stream: &mut T,
+    path: &str,
+    fields: &Fields,
+    depth: usize,
+) -> Result<()> {
+    for (key, value) in fields.iter() {
+        write_nested(output_stream, &format!("{}.{}", path, key), value, depth)?;
+    }
+    Ok(())
+}
+
+/*
+    Writes a field, element or entry reached through `path`. Structs get a `path:` header
+    with their fields indented below it, arrays and maps one line per element or entry.
+*/
+fn write_nested<T: Write>(
+    output_stream: &mut T,
+    path: &str,
+    value: &Value,
+    depth: usize,
+) -> Result<()> {
+    let indent = "    ".repeat(depth);
+    match value {
+        Value::Struct(nested) => {
+            writeln!(output_stream, "{}{}:", indent, path)?;
+            write_fields(output_stream, path, &nested.fields, depth + 1)
+        }
+        Value::Array(values) => write_elements(output_stream, path, values, depth),
+        Value::Map(entries) => write_entries(output_stream, path, entries, depth),
+        _ => writeln!(output_stream, "{}{} = {}", indent, path, value),
+    }
+}
+
+fn write_elements<T: Write>(
+    output_stream: &mut T,
+    path: &str,
+    values: &[Value],
+    depth: usize,
+) -> Result<()> {
+    for (index, value) in values.iter().enumerate() {
+        write_nested(output_stream, &format!("{}[{}]", path, index), value, depth)?;
+    }
+    Ok(())
+}
+
+fn write_entries<T: Write>(
+    output_stream: &mut T,
+    path: &str,
+    entries: &Fields,
+    depth: usize,
+) -> Result<()> {
+    for (key, value) in entries.iter() {
+        write_nested(output_stream, &format!("{}[{}]", path, key), value, depth)?;
+    }
+    Ok(())
+}
@@ -2,28 +2,105 @@
     Tests to validate the output of some source codes
 */
 
-use my_project::interpreter::{Interpreter};
+use my_project::bytecode::Slots;
+use my_project::compiler::Compiler;
+use my_project::error::{ErrorKind, NekoError};
+use my_project::interpreter::{Interpreter, InterruptHandle};
+use my_project::lexeme::tokenize;
+use my_project::native;
+use my_project::optimizer::Optimization;
+use my_project::repl::Repl;
+use my_project::value::Value;
+use std::collections::HashSet;
+use std::io::BufRead;
 
 /*
-    Utility function to check that the interpreter produces the right output for a given input
+    Runs a program on an interpreter, either on the stack machine or by walking it
+*/
+type Run<R> = fn(&mut Interpreter<Vec<u8>, R>, &str) -> Result<(), NekoError>;
+
+/*
+    A way to run programs: on the stack machine, optimised as given, or by walking them
+*/
+#[derive(Clone, Copy)]
+enum Path {
+    Compiled(Optimization),
+    Walked,
+}
+
+const EVERY_PATH: &[Path] = &[
+    Path::Compiled(Optimization::Off),
+    Path::Compiled(Optimization::Safe),
+    Path::Compiled(Optimization::Full),
+    Path::Walked,
+];
+
+/*
+    Full optimisation drops stores nothing in the program reads, so tests reading variables after a run leave it out
+*/
+const KEEPING_VARIABLES: &[Path] = &[Path::Compiled(Optimization::Off), Path::Compiled(Optimization::Safe), Path::Walked];
+
+/*
+    Utility function to run a test on each of `paths`. Each time `test` is given a fresh interpreter made by `setup`
+    and the way to run programs on it.
+*/
+fn check_on_paths<R: BufRead>(
+    paths: &[Path],
+    setup: impl Fn() -> Interpreter<Vec<u8>, R>,
+    mut test: impl FnMut(&mut Interpreter<Vec<u8>, R>, Run<R>),
+){
+    let compiled: Run<R> = |interpreter, source_code| interpreter.run(source_code).map(|_| ());
+    let walked: Run<R> = |interpreter, source_code| interpreter.run_tree_walking(source_code).map(|_| ());
+    for path in paths {
+        let mut interpreter = setup();
+        let run = match *path {
+            Path::Compiled(optimization) => {
+                interpreter.set_optimization(optimization);
+                compiled
+            }
+            Path::Walked => walked,
+        };
+        test(&mut interpreter, run);
+    }
+}
+
+/*
+    Utility function to check that the interpreter produces the right output for a given input, on every path
 */
 fn check_input_output_eq(source_code: &str, expected_output: &str){
-    let output_stream: Box<Vec<u8>> = Box::new(Vec::new());
-    let mut interpreter = Interpreter::new_with_output_stream(output_stream);
-    let output_stream = interpreter.run(source_code).unwrap();
-    let actual_ouput = std::str::from_utf8(output_stream.as_slice()).unwrap();
-    assert_eq!(expected_output, actual_ouput);
+    check_on_paths(EVERY_PATH, || Interpreter::new_with_output_stream(Vec::new()), |interpreter, run| {
+        run(interpreter, source_code).unwrap();
+        assert_eq!(expected_output, std::str::from_utf8(interpreter.output_stream()).unwrap());
+    });
+}
+
+/*
+    Utility function to check that a program fails with the expected error on every path
+*/
+fn check_error_eq(source_code: &str, expected_error: &str){
+    check_on_paths(EVERY_PATH, || Interpreter::new_with_output_stream(Vec::new()), |interpreter, run| {
+        let error = run(interpreter, source_code).unwrap_err();
+        assert_eq!(expected_error, error.to_string());
+    });
+}
+
+/*
+    Utility function to get the output of a REPL session fed with the given lines, without prompts
+*/
+fn repl_output(input: &str) -> String{
+    let interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let mut repl = Repl::new(interpreter, false);
+    repl.run(input.as_bytes()).unwrap();
+    let mut interpreter = repl.into_interpreter();
+    std::str::from_utf8(interpreter.output_stream()).unwrap().to_owned()
 }
 
 /*
-    Utility function to check that the interpreter produces at least part of the right output for a given input
+    Utility function to check the output of a REPL session fed with the given lines, without prompts
 */
-fn check_input_output_contains(source_code: &str, expected_part: &str){
-    let output_stream: Box<Vec<u8>> = Box::new(Vec::new());
-    let mut interpreter = Interpreter::new_with_output_stream(output_stream);
-    let output_stream = interpreter.run(source_code).unwrap();
-    let actual_ouput = std::str::from_utf8(output_stream.as_slice()).unwrap();
-    assert!(actual_ouput.contains(expected_part));
+fn check_repl_output_eq(input: &str, expected_output: &str){
+    let actual_ouput = repl_output(input);
+    assert_eq!(expected_output, actual_ouput);
 }
 
 #[test]
@@ -88,12 +165,9 @@ pub fn test_struct(){
         print game_scores
     ";
 
-    let expected_output_a = "game_scores.team_a = 5\n";
-    let expected_output_b = "game_scores.team_b = 3\n";
+    let expected_output = "game_scores.team_a = 5\ngame_scores.team_b = 3\n";
 
-    // Members of the struct may be displayed as variables are stored in hash maps, which are unordered
-    check_input_output_contains(source_code, expected_output_a);
-    check_input_output_contains(source_code, expected_output_b);
+    check_input_output_eq(source_code, expected_output)
 }
 
 #[test]
@@ -112,3 +186,1402 @@ pub fn test_loop(){
 
     check_input_output_eq(source_code, expected_output)
 }
+
+#[test]
+pub fn test_nested_struct(){
+    let source_code = "
+        float thickness 1.5
+        struct line
+            start struct
+                x 1
+                y 2
+            endstruct
+            width thickness
+            label string dashed line endstring
+        endstruct
+
+        print line
+        print line.start.y
+    ";
+
+    let expected_output = "line.start:
+    line.start.x = 1
+    line.start.y = 2
+line.width = 1.5
+line.label = dashed line
+2
+";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_struct_keeps_declaration_order(){
+    let source_code = "
+        struct point3d
+            z 3
+            x 1
+            y 2
+            x 4
+        endstruct
+
+        print point3d
+    ";
+
+    let expected_output = "point3d.z = 3\npoint3d.x = 4\npoint3d.y = 2\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_function_with_parameters(){
+    let source_code = "
+        function sum a b with
+            add a b
+            return a
+        end
+        var x 4
+        call sum x 6 into total
+        print total
+        print x
+    ";
+
+    let expected_output = "10\n4\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_method(){
+    let source_code = "
+        struct Point
+            x 0
+            y 0
+        endstruct
+
+        method Point x_coord with
+            return self.x
+        end
+
+        method Point scaled factor with
+            call self.x_coord into result
+            mul result factor
+            return result
+        end
+
+        method Point mirror with
+            call self.x_coord into self.y
+        end
+
+        struct p as Point
+            x 3
+        endstruct
+
+        call p.scaled 10 into d
+        print d
+        call p.mirror
+        print p
+    ";
+
+    let expected_output = "30\np.x = 3\np.y = 3\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_methods_sharing_a_name(){
+    let source_code = "
+        struct A
+            n 0
+        endstruct
+
+        struct B
+            n 1
+        endstruct
+
+        method A go n with
+            return n
+        end
+
+        method B go with
+            return self.n
+        end
+
+        function f p with
+            call p.go into result
+            print result
+        end
+
+        struct b as B
+            n 1
+        endstruct
+
+        call f b
+    ";
+
+    check_input_output_eq(source_code, "1\n")
+}
+
+#[test]
+pub fn test_enum_match(){
+    let source_code = "
+        enum Shape Circle r | Rect w h | Empty endenum
+
+        call Shape.Rect 3 4 into shape
+        print shape
+        match shape
+            case Circle r
+                print r
+            case Rect w h
+                mul w h
+                print w
+            case Empty
+        endmatch
+
+        call Shape.Empty into nothing
+        match nothing
+            case Circle r
+                print r
+            case _
+                print nothing
+        endmatch
+    ";
+
+    let expected_output = "Shape.Rect(3, 4)\n12\nShape.Empty\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_match_must_cover_every_variant(){
+    let source_code = "
+        enum Shape Circle r | Rect w h endenum
+        call Shape.Circle 1 into shape
+        match shape
+            case Circle r
+                print r
+        endmatch
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert!(error.to_string().starts_with("Match on Shape does not cover: Rect"), "{}", error);
+    let error = interpreter.run_tree_walking(source_code).unwrap_err();
+    assert!(error.to_string().starts_with("Match on Shape does not cover: Rect"), "{}", error);
+}
+
+#[test]
+pub fn test_multiple_return_values(){
+    let source_code = "
+        function divmod a b with
+            var q 0
+            add q a
+            div q b
+            var r 0
+            add r q
+            mul r b
+            sub a r
+            return q a
+        end
+
+        call divmod 17 5 into q r
+        print q
+        print r
+        call divmod 9 4 into pair
+        print pair
+        print pair.1
+    ";
+
+    let expected_output = "3\n2\n(2, 1)\n1\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_destructuring_needs_as_many_names_as_values(){
+    let source_code = "
+        function pair n with
+            if n > 0
+                return n n
+            end
+            return n
+        end
+        call pair 1 into a b
+        call pair 1 into a b c
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Cannot bind (1, 1) to 3 names at line 9, column 30", error.to_string());
+    let error = interpreter.run_tree_walking(source_code).unwrap_err();
+    assert_eq!("Cannot bind (1, 1) to 3 names at line 9, column 30", error.to_string());
+}
+
+#[test]
+pub fn test_tuple(){
+    let source_code = "
+        tuple point (1, 2.5, 3)
+        print point
+        print point.1
+    ";
+
+    let expected_output = "(1, 2.5, 3)\n2.5\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_tuple_with_quoted_commas(){
+    let source_code = "
+        tuple pair (\"a, b\", 1)
+        print pair.0
+        print pair.1
+    ";
+
+    let expected_output = "a, b\n1\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_nested_tuple(){
+    let source_code = "
+        tuple nested ((1, 2), 3)
+        print nested
+        print nested.0.1
+        print nested.1
+    ";
+
+    let expected_output = "((1, 2), 3)\n2\n3\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_string_builtins(){
+    let source_code = "
+        string greeting Grüße, Welt endstring
+        len greeting into n
+        print n
+        upper greeting into loud
+        print loud
+        substr greeting 0 5 into first
+        concat first \"!\" into first
+        print first
+        index_of greeting \"Welt\" into at
+        print at
+        split \"a,b,c\" \",\" into parts
+        join parts \" | \" into joined
+        replace joined \"|\" \"/\" into joined
+        print joined
+        trim \"  padded  \" into padded
+        print padded
+        starts_with greeting \"Grü\" into yes
+        ends_with greeting \"Grü\" into no
+        print yes
+        print no
+        repeat \"ab\" 3 into abab
+        print abab
+        lower abab into abab
+        print abab
+    ";
+
+    let expected_output = "11\nGRÜSSE, WELT\nGrüße!\n7\na / b / c\npadded\n1\n0\nababab\nababab\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_string_index_out_of_bounds(){
+    let source_code = "
+        string word neko endstring
+        substr word 1 9 into part
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Index 9 is out of bounds for length 4 at line 3, column 23", error.to_string());
+}
+
+#[test]
+pub fn test_formatted_print(){
+    let source_code = "
+        var x 5
+        float avg 2.71828
+        var color 255
+        struct p
+            x 3
+            y -4
+        endstruct
+        print \"x = {x}, avg = {avg:.2}\"
+        print \"[{x:>4}] [{x:<4}] [{x:*^5}] [{x:03}]\"
+        print \"{color:x} {color:X} {x:b}\"
+        print \"{{x}} = {p.x * 2 + p.y} {(x + 1) / 2}\"
+    ";
+
+    let expected_output = "x = 5, avg = 2.72\n[   5] [5   ] [**5**] [005]\nff FF 101\n{x} = 2 3\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_formatted_print_unknown_name(){
+    let source_code = "
+        print \"total = {total}\"
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Unknown value: total at line 2, column 15", error.to_string());
+}
+
+#[test]
+pub fn test_foreach(){
+    let source_code = "
+        array numbers 5 4 8 15 16 23
+        var total 0
+        foreach n in numbers
+            if n == 15
+                continue
+            end
+            if n > 20
+                break
+            end
+            add total n
+        end
+        print total
+
+        foreach i c in \"ねこ\"
+            print \"{i}: {c}\"
+        end
+
+        map ages alice 31 bob 27 endmap
+        foreach name age in ages
+            print \"{name} is {age}\"
+        end
+
+        struct point
+            x 1
+            y 2
+        endstruct
+        foreach field value in point
+            print \"{field} = {value}\"
+        end
+    ";
+
+    let expected_output = "28\n0: ね\n1: こ\nalice is 31\nbob is 27\nx = 1\ny = 2\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_map_set_adds_and_replaces_entries(){
+    let source_code = "
+        map ages alice 31 endmap
+        set ages.bob 27
+        set ages.alice 32
+        add ages.bob 1
+        print ages
+        foreach name age in ages
+            print \"{name} is {age}\"
+        end
+    ";
+
+    let expected_output = "ages[alice] = 32\nages[bob] = 28\nalice is 32\nbob is 28\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_break_outside_loop(){
+    let source_code = "
+        var x 1
+        break
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("break or continue outside of a loop at line 3, column 9", error.to_string());
+}
+
+#[test]
+pub fn test_array_builtins(){
+    let source_code = "
+        array numbers 6 15 4 23 8 16 42
+        sort numbers into sorted
+        sort_desc numbers into descending
+        reverse descending into ascending
+        print \"{sorted} {descending} {ascending}\"
+        binary_search sorted 16 into at
+        binary_search sorted 5 into missing
+        contains numbers 8 into has_eight
+        index_of numbers 23 into where
+        print \"{at} {missing} {has_eight} {where}\"
+        sum numbers into total
+        min numbers into low
+        max numbers into high
+        avg numbers into mean
+        print \"{total} {low} {high} {mean:.2}\"
+        slice sorted 1 3 into middle
+        fill 2 0.5 into halves
+        concat middle halves into mixed
+        sum mixed into mixed_total
+        max mixed into mixed_max
+        print \"{mixed} {mixed_total} {mixed_max}\"
+    ";
+
+    let expected_output = "[4, 8, 15, 16, 23, 42] [42, 23, 16, 15, 8, 4] [4, 8, 15, 16, 23, 42]
+3 -1 1 2
+108 4 42 18.00
+[8, 15, 0.5, 0.5] 24 15
+";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_array_builtins_on_empty_array(){
+    let source_code = "
+        fill 0 1 into empty
+        sum empty into total
+        print total
+        min empty into low
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Cannot take the min of an empty array at line 5, column 13", error.to_string());
+}
+
+#[test]
+pub fn test_constants(){
+    let source_code = "
+        const LIMIT 3
+        const RATE 1.5
+        const GREETING \"hello\"
+        var total 10
+        add total LIMIT
+        float price 2.0
+        mul_f price RATE
+        var i 0
+        loop i < LIMIT
+            add i 1
+        end
+        print \"{GREETING} {total} {price} {i} {LIMIT * 2}\"
+    ";
+
+    let expected_output = "hello 13 3 4 6\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_assign_to_constant(){
+    let source_code = "
+        const LIMIT 3
+        var x 1
+        add LIMIT x
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Cannot assign to constant LIMIT at line 4, column 9", error.to_string());
+
+    let source_code = "
+        const NAMES array 2 3 1
+        sort NAMES into NAMES
+    ";
+
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Cannot assign to constant NAMES at line 3, column 25", error.to_string());
+}
+
+#[test]
+pub fn test_method_changing_constant(){
+    let source_code = "
+        struct Greeter name \"neko\" endstruct
+        method Greeter shout with
+            upper self.name into self.name
+        end
+        const GREETER struct as Greeter endstruct
+        call GREETER.shout
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Cannot assign to constant GREETER at line 7, column 14", error.to_string());
+}
+
+#[test]
+pub fn test_type_annotations(){
+    let source_code = "
+        var x: int 5
+        float rate: float 1.5
+        function scale a: int b: float -> float with
+            float result 2.0
+            mul_f result b
+            return result
+        end
+        call scale x rate into scaled
+        print \"{x} {scaled}\"
+    ";
+
+    let expected_output = "5 3\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_check_before_running(){
+    let programs = [
+        ("print \"start\"\nvar x: float 5", "x is declared float but given int at line 2, column 1"),
+        ("print \"start\"\nprint total", "Unknown value: total at line 2, column 7"),
+        ("function f a b with\nreturn a\nend\ncall f 1", "f expects 2 arguments, found 1 at line 4, column 6"),
+        ("function f a: int with\nreturn a\nend\ncall f 1.5", "Expected int for a, found float at line 4, column 8"),
+        ("function f -> int with\nreturn \"text\"\nend", "Expected int to be returned, found string at line 2, column 1"),
+        ("float y 1.5\nvar x 1\nadd x y", "Expected int but y is float at line 3, column 7"),
+        ("function f with\nprint x\nend\nvar x 1", "Unknown value: x at line 2, column 7"),
+    ];
+
+    for (source_code, expected_error) in programs {
+        let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+        let error = interpreter.run(source_code).unwrap_err();
+        assert_eq!(expected_error, error.to_string());
+        assert!(interpreter.run("").unwrap().is_empty(), "nothing should run before the error");
+        assert_eq!(expected_error, my_project::checker::check(source_code).unwrap_err().to_string());
+    }
+}
+
+#[test]
+pub fn test_none(){
+    let source_code = "
+        map ages alice 31 endmap
+        print ages.bob
+        get ages \"alice\" into alice
+        get ages \"bob\" into bob
+        is_none bob into missing
+        is_some alice into found
+        unwrap_or bob 0 into age
+        print \"{alice} {bob} {missing} {found} {age}\"
+        array numbers 1 7
+        pop numbers into last
+        pop numbers into nothing
+        get numbers 3 into outside
+        var maybe: int? none
+        print \"{last} {nothing} {outside} {maybe}\"
+    ";
+
+    let expected_output = "none\n31 none 1 1 0\n7 none none none\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_none_in_arithmetic(){
+    let source_code = "
+        array numbers 0
+        pop numbers into last
+        var total 1
+        add total last
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Cannot use none in arithmetic: last is none at line 5, column 19", error.to_string());
+
+    let error = interpreter.run("print \"{last + 1}\"").unwrap_err();
+    assert_eq!("Cannot apply + to none at line 1, column 7", error.to_string());
+}
+
+#[test]
+pub fn test_true_and_false_are_one_and_zero(){
+    let source_code = "
+        var t true
+        var f: bool false
+        set f true
+        if t == true
+            print t
+        end
+        print f
+    ";
+
+    let expected_output = "1\n1\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_typed_arrays(){
+    let source_code = "
+        array<float> prices 3 1.5 2 3.25
+        array names 2 \"alice\" \"bob\"
+        array<bool> flags 3 true false 1
+        struct origin x 0 y 0 endstruct
+        array points 2
+            origin
+            struct as origin x 3 y 4 endstruct
+        array mixed 3 1 2.5 \"three\"
+        print prices
+        print names
+        print flags
+        print points
+        sum prices into total
+        print \"{total} {mixed}\"
+    ";
+
+    let expected_output = "prices[0] = 1.5
+prices[1] = 2
+prices[2] = 3.25
+names[0] = alice
+names[1] = bob
+flags[0] = 1
+flags[1] = 0
+flags[2] = 1
+points[0]:
+    points[0].x = 0
+    points[0].y = 0
+points[1]:
+    points[1].x = 3
+    points[1].y = 4
+6.75 [1, 2.5, three]
+";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_typed_array_element_mismatch(){
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run("array<float> xs 2 1.5 \"two\"").unwrap_err();
+    assert_eq!("Expected float elements in xs, found string at line 1, column 23", error.to_string());
+}
+
+#[test]
+pub fn test_struct_field_arithmetic(){
+    let source_code = "
+        struct point
+            x 1
+            y 2.5
+            label string start endstring
+        endstruct
+        var step 2
+        add point.x step
+        mul point.x 3
+        float half 0.5
+        add_f point.y half
+        set point.label \"moved\"
+        if point.x > 8
+            print \"{point.x} {point.y} {point.label}\"
+        end
+        var point.x 0
+        loop point.y < 4.5
+            add_f point.y 1.0
+            add point.x 1
+        end
+        print point
+    ";
+
+    let expected_output = "9 3 moved
+point.x = 3
+point.y = 6
+point.label = moved
+";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_unknown_field(){
+    let source_code = "
+        struct point x 1 endstruct
+        add point.z 1
+    ";
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Unknown field: point.z at line 3, column 13", error.to_string());
+
+    let source_code = "
+        struct point x 1 endstruct
+        function shift p with
+            add p.z 1
+        end
+        call shift point
+    ";
+
+    let error = interpreter.run(source_code).unwrap_err();
+    assert_eq!("Unknown field: p.z at line 4, column 17", error.to_string());
+}
+
+#[test]
+pub fn test_assignment_copies_values(){
+    let source_code = "
+        array numbers 3 1 2 3
+        set others numbers
+        pop others into last
+        struct box items numbers endstruct
+        pop numbers into last
+        print \"{numbers} {others} {box.items}\"
+        struct a x 1 endstruct
+        set b a
+        add b.x 10
+        print \"{a.x} {b.x}\"
+    ";
+
+    let expected_output = "[1, 2] [1, 2] [1, 2, 3]\n1 11\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_arguments_are_copies(){
+    let source_code = "
+        function grow p xs ages with
+            add p.x 100
+            pop xs into dropped
+            set ages.bob 99
+            return p xs ages
+        end
+        struct point x 1 endstruct
+        array numbers 2 5 6
+        map ages bob 27 endmap
+        call grow point numbers ages into grown shorter older
+        print \"{point.x} {numbers} {ages.bob}\"
+        print \"{grown.x} {shorter} {older.bob}\"
+    ";
+
+    let expected_output = "1 [5, 6] 27\n101 [5] 99\n";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_foreach_items_are_copies(){
+    let source_code = "
+        struct first x 1 endstruct
+        struct second x 2 endstruct
+        array points 2 first second
+        foreach p in points
+            add p.x 10
+        end
+        print points
+    ";
+
+    let expected_output = "points[0]:
+    points[0].x = 1
+points[1]:
+    points[1].x = 2
+";
+
+    check_input_output_eq(source_code, expected_output)
+}
+
+#[test]
+pub fn test_method_changes_its_receiver(){
+    let source_code = "
+        struct Counter count 0 endstruct
+        method Counter bump with
+            add self.count 1
+        end
+        struct c as Counter endstruct
+        set d c
+        call c.bump
+        call c.bump
+        print \"{c.count} {d.count}\"
+    ";
+
+    check_input_output_eq(source_code, "2 0\n")
+}
+
+#[test]
+pub fn test_repl_keeps_state_between_lines(){
+    let input = "var x 5
+x
+x * 2 + 1
+struct p x 1 y 2.5 endstruct
+p
+";
+
+    check_repl_output_eq(input, "5\n11\np.x = 1\np.y = 2.5\n")
+}
+
+#[test]
+pub fn test_repl_multi_line_blocks(){
+    let input = "var i 0
+loop i < 2
+    add i 1
+end
+i
+function greet name with
+    print \"hi {name}\"
+end
+call greet \"neko\"
+";
+
+    check_repl_output_eq(input, "3\nhi neko\n")
+}
+
+#[test]
+pub fn test_repl_errors_keep_running(){
+    let input = "print missing
+var x 1
+div x 0
+:nope
+x
+";
+
+    check_repl_output_eq(input, "Error: Unknown value: missing at line 1, column 7
+Error: Division by zero at line 1, column 7
+Error: Unknown command: :nope (:help lists the commands)
+1
+")
+}
+
+#[test]
+pub fn test_repl_meta_commands(){
+    let input = "const LIMIT 3
+var b 2
+array a 2 1 2
+struct p x 1 endstruct
+function scale a: int f: float -> float with
+    return f
+end
+:vars
+:funcs
+:reset
+:vars
+:load missing.nek
+";
+
+    // Why the file could not be read is up to the OS, so only what comes before it is checked
+    let output = repl_output(input);
+    let expected_output = "const LIMIT = 3
+a = [1, 2]
+b = 2
+p = p {x: 1}
+scale a: int f: float -> float
+Error: Could not read file missing.nek";
+    assert!(output.starts_with(expected_output), "{}", output);
+    assert_eq!(1, output[expected_output.len()..].lines().count(), "{}", output);
+}
+
+#[test]
+pub fn test_examples_on_both_paths(){
+    let mut examples: Vec<_> = std::fs::read_dir("example_codes").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    examples.sort();
+    for example in examples {
+        let source_code = std::fs::read_to_string(example).unwrap();
+        let mut walked = Interpreter::new_with_output_stream(Vec::new());
+        walked.run_tree_walking(&source_code).unwrap();
+        let expected_output = std::str::from_utf8(walked.output_stream()).unwrap().to_owned();
+        check_input_output_eq(&source_code, &expected_output);
+    }
+}
+
+#[test]
+pub fn test_loop_control_flow_on_both_paths(){
+    // The last pass a loop makes after its condition fails hands break and continue to the enclosing loop
+    let source_code = "
+        var i 0
+        var j 0
+        loop i < 3
+            add i 1
+            set j 0
+            loop j < 2
+                add j 1
+                if i == 2
+                    continue
+                end
+                print \"{i} {j}\"
+            end
+        end
+        var k 0
+        loop k < 10
+            add k 1
+            switch k
+                case 2
+                    continue
+                case 4
+                    print k
+                    break
+            endswitch
+            if k == 5
+                break
+            end
+        end
+        array xs 3 1 2 3
+        array ys 2 10 20
+        foreach x in xs
+            foreach y in ys
+                if y == 20
+                    break
+                end
+                print \"{x} {y}\"
+            end
+            if x == 2
+                break
+            end
+        end
+        function first_even numbers with
+            foreach n in numbers
+                set rest n
+                div rest 2
+                mul rest 2
+                if rest == n
+                    return n
+                end
+            end
+            return -1
+        end
+        array values 4 3 5 8 9
+        call first_even values into even
+        print even
+    ";
+
+    let expected_output = "1 1\n1 2\n1 3\n3 1\n3 2\n3 3\n4 1\n4 2\n4 3\n4\n1 10\n2 10\n8\n";
+
+    check_input_output_eq(source_code, expected_output);
+    check_input_output_eq("var i 0 loop i < 1 add i 1 break end print i", "1\n");
+    check_input_output_eq("var i 0 loop i < 1 add i 1 end print i", "2\n");
+}
+
+#[test]
+pub fn test_arithmetic_on_both_paths(){
+    let source_code = "
+        var a 7
+        var b 2
+        pow a b
+        div a b
+        float f 1.5
+        mul_f f 2.0
+        struct p x 1 y 2 endstruct
+        add p.x a
+        const k 3
+        add a k
+        print a
+        print f
+        print p.x
+    ";
+
+    check_input_output_eq(source_code, "27\n3\n25\n");
+    check_error_eq("var a 1 var b 0 div a b", "Division by zero at line 1, column 23");
+    check_error_eq("var a 1 float f 2.0 add a f", "Expected int but f is float at line 1, column 27");
+    check_error_eq("var a 1 float f 2.0 add f a", "Expected int but f is float at line 1, column 25");
+    check_error_eq("const k 1 add k 1", "Cannot assign to constant k at line 1, column 11");
+    check_error_eq("array xs 1 1 get xs 5 into n add n 1",
+        "Cannot use none in arithmetic: n is none at line 1, column 34");
+    check_error_eq("var a 1 add a missing", "Unknown value: missing at line 1, column 15");
+    check_error_eq("var a 2147483647 add a 1", "Integer overflow in add at line 1, column 22");
+    check_error_eq("var a 65536 mul a a", "Integer overflow in mul at line 1, column 17");
+    check_error_eq("var a -2147483648 var b -1 div a b", "Integer overflow in div at line 1, column 32");
+    check_error_eq("var a 2 var b -1 pow a b", "Negative exponent: -1 at line 1, column 22");
+    check_error_eq("var a -2147483648 abs a", "Integer overflow in abs at line 1, column 23");
+}
+
+#[test]
+pub fn test_names_resolve_before_running(){
+    let compile = |source_code: &str| Compiler::compile(&tokenize(source_code), &Slots::default(), &HashSet::new())
+        .map(|_| ())
+        .map_err(|error| error.to_string());
+
+    assert_eq!(Ok(()), compile("var i 0 loop i < 2 if i > 0 print x end set x i add i 1 end"));
+    assert_eq!(Ok(()), compile("function f a with var b 1 add b a return b end const k 2 print k"));
+    assert_eq!(Err("Unknown value: total at line 1, column 32".to_owned()),
+        compile("var i 0 loop i < 3 add i 1 add total i end"));
+    assert_eq!(Err("Unknown value: y at line 1, column 29".to_owned()),
+        compile("var x 1 function f with add y 1 end"));
+    assert_eq!(Err("Unknown value: p at line 1, column 15".to_owned()), compile("var x 1 print \"{p.x}\""));
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    interpreter.run("var x 1 float f 2.0").unwrap();
+    interpreter.run("add x 1 add_f f 0.5 print x print f").unwrap();
+    let error = interpreter.run("print x print missing").unwrap_err();
+    assert_eq!("Unknown value: missing at line 1, column 15", error.to_string());
+    assert_eq!("2\n2.5\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+}
+
+#[test]
+pub fn test_optimiser(){
+    let source_code = "
+        var x 5
+        add x 3
+        mul x 2
+        if x > 100
+            print \"big\"
+        else
+            print x
+        end
+        var unused 4
+        float f 1.5
+        mul_f f 2.0
+        var root 17
+        sqrt root
+        function scale a with
+            var scratch 1
+            add scratch 2
+            var kept 7
+            return a
+            print kept
+        end
+        call scale root into y
+        print f
+        print y
+    ";
+    check_input_output_eq(source_code, "16\n3\n4\n");
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    let plain = interpreter.compile(source_code).unwrap().to_string();
+    interpreter.set_optimization(Optimization::Full);
+    let optimised = interpreter.compile(source_code).unwrap().to_string();
+    assert_eq!(optimised, "\
+0000 push 5
+0001 store x
+0002 push 8
+0003 store x
+0004 push 16
+0005 store x
+0006 print x
+0007 push 1.5
+0008 store_local f
+0009 push 3
+0010 store f
+0011 define_function scale
+0012 push 4
+0013 call scale 1 into y
+0014 print f
+0015 print y
+
+function scale:
+0000 push a
+0001 return 1
+");
+    assert!(plain.contains("jump_unless > "));
+    assert!(plain.contains("store unused"));
+
+    // The variables of a program stay as they were unless it is only run once
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    interpreter.set_optimization(Optimization::Safe);
+    interpreter.run("var unused 4 var x 1 add x 1").unwrap();
+    assert_eq!(Some(Value::Int(4)), interpreter.get("unused"));
+    assert_eq!(Some(Value::Int(2)), interpreter.get("x"));
+
+    // Constants are folded like numbers, even past a loop when every run defines them
+    let source_code = "const K 3 var x 1 add x K print x var i 0 loop i < 5 add i K end print x";
+    check_input_output_eq(source_code, "4\n4\n");
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    interpreter.set_optimization(Optimization::Full);
+    let optimised = interpreter.compile(source_code).unwrap().to_string();
+    assert!(optimised.starts_with("0000 push 3\n0001 define_constant K\n0002 push 1\n0003 store x\n0004 push 4\n0005 store x\n"), "{}", optimised);
+    assert!(optimised.contains("add i 3\n") && !optimised.contains("add i K"), "{}", optimised);
+}
+
+#[test]
+pub fn test_fuel_stops_endless_loops(){
+    let source_code = "
+        var x 0
+        var step 0
+        loop x < 5
+            add x step
+        end
+    ";
+    let setup = || {
+        let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+        interpreter.set_fuel(Some(1000));
+        interpreter
+    };
+    check_on_paths(EVERY_PATH, setup, |interpreter, run| {
+        let error = run(interpreter, source_code).unwrap_err();
+        assert_eq!(ErrorKind::OutOfFuel, error.kind);
+        assert!(error.to_string().starts_with("Out of fuel after 1000 steps at "), "{}", error);
+        assert_eq!(1000, interpreter.fuel_used());
+
+        // The interpreter can run again, and a program that finishes reports what it used
+        run(interpreter, "print x").unwrap();
+        let used = interpreter.fuel_used();
+        assert!(used > 0 && used < 1000, "{}", used);
+        assert_eq!("0\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+    });
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    interpreter.run("var x 0 loop x < 5 add x 1 end end").unwrap();
+    assert!(interpreter.fuel_used() > 5);
+    let error = interpreter.run("print missing").unwrap_err();
+    assert_eq!(ErrorKind::Other, error.kind);
+}
+
+#[test]
+pub fn test_memory_limit(){
+    let programs = [
+        // Strings and arrays built by builtins are checked before they are allocated
+        ("string s abc endstring repeat s 2000000000 into big", 1, 24),
+        ("fill 2000000000 0 into zeros", 1, 1),
+        // Copies add up, whether they are made by assignments, arrays or struct fields
+        ("array a 4 1 2 3 4 var i 0 loop i < 100 concat a a into a add i 1 end end", 1, 56),
+        ("string s abcdefgh endstring var i 0 loop i < 100 concat s s into s add i 1 end end", 1, 66),
+        ("array a 2 1 2 var i 0 loop i < 100 struct pair left a right a endstruct set a pair add i 1 end end", 1, 43),
+        // Every call in progress costs memory, so recursion can not go on forever
+        ("function down n with add n 1 call down n into r end call down 0", 1, 35),
+    ];
+    let setup = || {
+        let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+        interpreter.set_memory_limit(Some(200_000));
+        interpreter
+    };
+    for (source_code, line, column) in programs {
+        let mut outcomes = vec![];
+        check_on_paths(EVERY_PATH, setup, |interpreter, run| {
+            let error = run(interpreter, source_code).unwrap_err();
+            assert_eq!(ErrorKind::OutOfMemory, error.kind, "{}", error);
+            assert!(error.to_string().starts_with("Out of memory: "), "{}", error);
+            assert_eq!(Some((line, column)), error.span.map(|span| (span.line, span.column)), "{}", source_code);
+            assert!(interpreter.memory_used() <= 200_000);
+
+            // The interpreter can go on running smaller programs
+            run(interpreter, "var small 1 print small").unwrap();
+            outcomes.push(error.to_string());
+        });
+        assert!(outcomes.iter().all(|outcome| *outcome == outcomes[0]), "{:?}", outcomes);
+    }
+
+    let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+    interpreter.run("string s abcdefgh endstring").unwrap();
+    let used = interpreter.memory_used();
+    interpreter.run("array a 3 1 2 3").unwrap();
+    assert!(interpreter.memory_used() > used);
+    interpreter.run("pop a into last pop a into last pop a into last").unwrap();
+    interpreter.set_memory_limit(Some(interpreter.memory_used()));
+    interpreter.run("set s s").unwrap();
+    assert!(interpreter.run("set t s").is_err());
+}
+
+#[test]
+pub fn test_unbounded_recursion(){
+    // The default limit leaves room on the stack of a test thread, even in a debug build
+    let programs = [
+        ("function f n with add n 1 call f n into r end call f 0", 32),
+        ("function f n with if n > -1 add n 1 call f n end end call f 0", 42),
+    ];
+    for (source_code, column) in programs {
+        check_on_paths(EVERY_PATH, || Interpreter::new_with_output_stream(Vec::new()), |interpreter, run| {
+            let error = run(interpreter, source_code).unwrap_err();
+            assert_eq!(ErrorKind::StackOverflow, error.kind, "{}", error);
+            let expected = format!("Stack overflow: more than 200 calls in progress at line 1, column {}", column);
+            assert_eq!(expected, error.to_string());
+
+            // The calls cut short by the overflow are not counted against the next run
+            run(interpreter, "function g with var one 1 print one end call g").unwrap();
+        });
+    }
+
+    let setup = || {
+        let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+        interpreter.set_max_call_depth(3);
+        interpreter
+    };
+    check_on_paths(EVERY_PATH, setup, |interpreter, run| {
+        run(interpreter, "function down n with if n > 0 sub n 1 call down n end end call down 2").unwrap();
+        let error = run(interpreter, "function down n with if n > 0 sub n 1 call down n end end call down 3").unwrap_err();
+        assert_eq!(ErrorKind::StackOverflow, error.kind, "{}", error);
+    });
+}
+
+#[test]
+pub fn test_interrupts_and_timeouts(){
+    let endless = "var x 0 var step 0 loop x < 5 add x step end end";
+    check_on_paths(EVERY_PATH, || Interpreter::new_with_output_stream(Vec::new()), |interpreter, run| {
+        // Interrupted from another thread
+        let handle: InterruptHandle = interpreter.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            handle.interrupt();
+        });
+        let error = run(interpreter, endless).unwrap_err();
+        interrupter.join().unwrap();
+        assert_eq!(ErrorKind::Interrupted, error.kind);
+        assert!(error.to_string().starts_with("Interrupted at "), "{}", error);
+
+        // The interrupt stops one run only, and the interpreter keeps what it had
+        run(interpreter, "add x 1 print x").unwrap();
+        assert_eq!("1\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+
+        // Interrupting before a run starts, as while it is checked and compiled, stops it
+        interpreter.interrupt_handle().interrupt();
+        let error = run(interpreter, "print x").unwrap_err();
+        assert_eq!(ErrorKind::Interrupted, error.kind);
+
+        // An interrupt the run ends without noticing does not stop the next one
+        let handle = interpreter.interrupt_handle();
+        interpreter.register_fn("cancel", move |_| {
+            handle.interrupt();
+            Ok(Value::None)
+        });
+        run(interpreter, "call cancel").unwrap();
+        run(interpreter, "print x").unwrap();
+
+        // Running out of time, including inside a function
+        interpreter.set_timeout(Some(std::time::Duration::from_millis(50)));
+        let error = run(interpreter, endless).unwrap_err();
+        assert_eq!(ErrorKind::Interrupted, error.kind);
+        assert!(error.to_string().starts_with("Interrupted after running out of time at "), "{}", error);
+        let used = interpreter.memory_used();
+        let error = run(interpreter, "function spin with var y 0 loop y < 1 end end end call spin").unwrap_err();
+        assert_eq!(ErrorKind::Interrupted, error.kind);
+        assert_eq!(used, interpreter.memory_used());
+        run(interpreter, "print x").unwrap();
+        assert_eq!("1\n1\n0\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+    });
+}
+
+#[test]
+pub fn test_native_functions(){
+    let source_code = "
+        var code 404
+        call http_status code into status
+        print status
+        call http_status 200 into status
+        print status
+        array codes 3 200 404 500
+        call count_errors codes into errors
+        print errors
+        float celsius 21.5
+        call to_fahrenheit celsius into fahrenheit
+        print fahrenheit
+        call log \"done\" true
+        call pair into left right
+        print \"{left} {right}\"
+    ";
+    let logged = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let setup = || {
+        let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+        interpreter.register_fn("http_status", |args| {
+            let (code,): (i32,) = native::arguments(args)?;
+            Ok(match code {
+                200 => "OK".into(),
+                404 => "Not Found".into(),
+                _ => Value::None,
+            })
+        });
+        interpreter.register_fn("count_errors", |args| {
+            let (codes,): (Vec<i32>,) = native::arguments(args)?;
+            Ok((codes.iter().filter(|&&code| code >= 400).count() as i32).into())
+        });
+        interpreter.register_fn("to_fahrenheit", |args| {
+            let (celsius,): (f32,) = native::arguments(args)?;
+            Ok((celsius * 9.0 / 5.0 + 32.0).into())
+        });
+        let log = std::rc::Rc::clone(&logged);
+        interpreter.register_fn("log", move |args| {
+            let (message, important): (String, bool) = native::arguments(args)?;
+            log.borrow_mut().push(format!("{} {}", message, important));
+            Ok(Value::None)
+        });
+        interpreter.register_fn("pair", |args| {
+            native::arguments::<()>(args)?;
+            Ok(Value::Tuple(vec![1.into(), "two".into()]))
+        });
+        interpreter
+    };
+    check_on_paths(KEEPING_VARIABLES, setup, |interpreter, run| {
+        run(interpreter, source_code).unwrap();
+        assert_eq!("Not Found\nOK\n2\n70.7\n1 two\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+        assert_eq!(vec!["done true".to_owned()], logged.take());
+
+        // Arguments are checked by the function, and its errors point at the call
+        for (source_code, message) in [
+            ("call http_status 1 2 into s", "http_status: Expected 1 arguments, found 2 at line 1, column 6"),
+            ("call http_status \"x\" into s", "http_status: Expected an integer, found x at line 1, column 6"),
+            ("call count_errors codes celsius", "count_errors: Expected 1 arguments, found 2 at line 1, column 6"),
+        ] {
+            assert_eq!(message, run(interpreter, source_code).unwrap_err().to_string());
+        }
+
+        // Functions are kept by a reset, and the names they return into are known to the checker
+        interpreter.reset();
+        run(interpreter, "call http_status 200 into s print s").unwrap();
+        assert!(run(interpreter, "call unknown_function 1").is_err());
+    });
+}
+
+#[test]
+pub fn test_host_reads_and_writes_variables(){
+    let setup = || {
+        let mut interpreter = Interpreter::new_with_output_stream(Vec::new());
+        interpreter.set_int("count", 4).unwrap();
+        interpreter.set_float("rate", 1.5).unwrap();
+        interpreter.set_str("name", "neko").unwrap();
+        interpreter.set_array("scores", vec![3.into(), 9.into(), 4.into()]).unwrap();
+        interpreter.set("flag", true).unwrap();
+        interpreter
+    };
+    check_on_paths(KEEPING_VARIABLES, setup, |interpreter, run| {
+        let source_code = "
+            add count 1
+            mul_f rate 2.0
+            concat name \"!\" into greeting
+            sum scores into total
+            struct point
+                x count
+                y total
+            endstruct
+            const limit 10
+            print \"{greeting} {flag}\"
+        ";
+        run(interpreter, source_code).unwrap();
+        assert_eq!("neko! 1\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+
+        assert_eq!(Some(5), interpreter.get_int("count"));
+        assert_eq!(Some(3.0), interpreter.get_float("rate"));
+        assert_eq!(Some("neko!"), interpreter.get_str("greeting"));
+        assert_eq!(Some(&[Value::Int(3), Value::Int(9), Value::Int(4)][..]), interpreter.get_array("scores"));
+        assert_eq!(Some(16), interpreter.get_int("point.y"));
+        assert_eq!(Some(10), interpreter.get_int("limit"));
+        let point = interpreter.get_struct("point").unwrap().clone();
+        assert_eq!("point", point.type_name);
+        assert_eq!(Some(&Value::Int(5)), point.fields.get("x"));
+
+        // Getters of the wrong type and missing names give nothing
+        assert_eq!(None, interpreter.get_int("rate"));
+        assert_eq!(None, interpreter.get_str("missing"));
+        assert_eq!(None, interpreter.get_struct("scores"));
+
+        // Fields can be set through paths, constants and missing fields can not be set
+        interpreter.set_int("point.x", 7).unwrap();
+        assert_eq!(Some(7), interpreter.get_int("point.x"));
+        interpreter.set_struct("origin", point).unwrap();
+        assert_eq!(Some(5), interpreter.get_int("origin.x"));
+        assert_eq!("Cannot assign to constant limit", interpreter.set_int("limit", 1).unwrap_err().to_string());
+        assert_eq!("Unknown field: point.z", interpreter.set_int("point.z", 1).unwrap_err().to_string());
+
+        let names: Vec<&str> = interpreter.iter_bindings().map(|(name, _)| name).collect();
+        assert_eq!(vec!["count", "rate", "name", "scores", "flag", "greeting", "total", "point", "origin"], names);
+    });
+}
+
+#[test]
+pub fn test_input_statements(){
+    let source_code = "
+        input a
+        input b
+        input c
+        input_int d
+        input_float e
+        input f
+        is_none f into eof
+        add a d
+        print \"{a} {b} {c} {e} {eof}\"
+    ";
+    let setup = || Interpreter::new_with_streams(Vec::new(), "42\n3.5\nhello there\n 7 \n2\n".as_bytes());
+    check_on_paths(EVERY_PATH, setup, |interpreter, run| {
+        run(interpreter, source_code).unwrap();
+        assert_eq!("49 3.5 hello there 2 1\n", std::str::from_utf8(interpreter.output_stream()).unwrap());
+    });
+
+    let setup = || Interpreter::new_with_streams(Vec::new(), "seven\n".as_bytes());
+    check_on_paths(EVERY_PATH, setup, |interpreter, run| {
+        let error = run(interpreter, "input_int count").unwrap_err();
+        assert_eq!("Expected an integer as input, found seven", error.message);
+        assert_eq!(1, error.span.unwrap().line);
+    });
+}